@@ -0,0 +1,45 @@
+use anyhow::{bail, Result};
+use reqwest::Client;
+use solus_rust_lib::proto::message::BlobPb;
+use twilight_model::channel::Attachment;
+
+// Gemini rejects requests over 20MB, leave some room for the rest of the prompt.
+const MAX_ATTACHMENT_SIZE: u64 = 15 * 1024 * 1024;
+
+/// Downloads a Discord attachment so it can be sent to the model inline.
+pub async fn blob_from_attachment(client: &Client, attachment: &Attachment) -> Result<BlobPb> {
+    let mime_type = match &attachment.content_type {
+        Some(content_type) => content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        None => bail!("{} has no content type.", attachment.filename),
+    };
+
+    Ok(BlobPb {
+        mime_type,
+        data: download_attachment(client, attachment).await?,
+    })
+}
+
+pub async fn download_attachment(client: &Client, attachment: &Attachment) -> Result<Vec<u8>> {
+    if attachment.size > MAX_ATTACHMENT_SIZE {
+        bail!(
+            "{} is too large ({} bytes).",
+            attachment.filename,
+            attachment.size
+        );
+    }
+
+    let data = client
+        .get(&attachment.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    Ok(data.to_vec())
+}
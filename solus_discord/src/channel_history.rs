@@ -0,0 +1,50 @@
+use anyhow::Result;
+use solus_rust_lib::{
+    gemini::api::new_content_pb, history::ContentsHistory, proto::message::ContentPb,
+};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::Message,
+    id::{
+        marker::{ChannelMarker, MessageMarker},
+        Id,
+    },
+};
+
+/// Messages before a mention that are sent along with it.
+const CHANNEL_HISTORY_LIMIT: u16 = 10;
+
+/// A mention's history: the messages in `channel_id` before `before`, then the turn so far.
+/// The channel is the record, nothing is saved to the session besides memories and usage,
+/// which are kept for `session_id`.
+pub async fn fetch(
+    twilight_client: &HttpClient,
+    channel_id: Id<ChannelMarker>,
+    before: Id<MessageMarker>,
+    session_id: String,
+) -> Result<ContentsHistory> {
+    let messages = twilight_client
+        .channel_messages(channel_id)
+        .before(before)
+        .limit(CHANNEL_HISTORY_LIMIT)?
+        .await?
+        .model()
+        .await?;
+
+    let contents = messages.iter().rev().map(content_from_message).collect();
+
+    Ok(ContentsHistory::new(contents).with_session_id(session_id))
+}
+
+/// A user's message as the model sees it, with who sent it.
+pub fn user_text(message: &Message) -> String {
+    format!("USER {}: \"{}\"", message.author.name, message.content)
+}
+
+fn content_from_message(message: &Message) -> ContentPb {
+    if message.author.bot {
+        new_content_pb("model".into(), message.content.clone())
+    } else {
+        new_content_pb("user".into(), user_text(message))
+    }
+}
@@ -1,8 +1,17 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{ atomic::{ AtomicU64, Ordering }, Arc, Mutex },
+};
 
 use async_trait::async_trait;
+use history::HistoryCommand;
+use imagine::ImagineCommand;
+use remember::RememberCommand;
 use solus::SolusCommand;
 use solus_rust_lib::data::CommandData as SolusCommandData;
+use stop::StopCommand;
+use upload::UploadCommand;
+use tokio_util::sync::CancellationToken;
 use twilight_http::{ client::InteractionClient, Client as TwilightClient };
 use twilight_interactions::command::{ CommandModel, CreateCommand };
 use twilight_model::{
@@ -11,12 +20,81 @@ use twilight_model::{
     id::{ marker::{ ApplicationMarker, InteractionMarker }, Id },
 };
 
+mod history;
+mod imagine;
+mod remember;
 mod solus;
+mod stop;
+mod upload;
+
+/// The generations running in each channel, keyed by channel id, so /stop can cancel them.
+#[derive(Default)]
+pub struct Generations {
+    next_id: AtomicU64,
+    channels: Mutex<HashMap<String, Vec<(u64, CancellationToken)>>>,
+}
+
+impl Generations {
+    /// Registers a generation in `channel_id`, it counts as running until the guard is dropped.
+    pub fn start(self: &Arc<Self>, channel_id: String) -> Generation {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancellation_token = CancellationToken::new();
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel_id.clone())
+            .or_default()
+            .push((id, cancellation_token.clone()));
+
+        Generation {
+            generations: self.clone(),
+            channel_id,
+            id,
+            cancellation_token,
+        }
+    }
+
+    /// Cancels every generation running in `channel_id`, false if there were none.
+    pub fn stop(&self, channel_id: &str) -> bool {
+        let running = self.channels.lock().unwrap().remove(channel_id).unwrap_or_default();
+        for (_, cancellation_token) in &running {
+            cancellation_token.cancel();
+        }
+        !running.is_empty()
+    }
+}
+
+/// A running generation, removed from its channel once it completes or fails.
+pub struct Generation {
+    generations: Arc<Generations>,
+    channel_id: String,
+    id: u64,
+    cancellation_token: CancellationToken,
+}
+
+impl Generation {
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+}
+
+impl Drop for Generation {
+    fn drop(&mut self) {
+        let mut channels = self.generations.channels.lock().unwrap();
+        if let Some(running) = channels.get_mut(&self.channel_id) {
+            running.retain(|(id, _)| *id != self.id);
+            if running.is_empty() {
+                channels.remove(&self.channel_id);
+            }
+        }
+    }
+}
 
 pub struct CommandHandlerData<'a> {
     pub channel: Channel,
     pub interaction_client: InteractionClient<'a>,
     pub solus_command_data: Arc<SolusCommandData>,
+    pub generations: Arc<Generations>,
 }
 
 #[async_trait]
@@ -32,6 +110,7 @@ pub trait CommandHandler {
 pub struct CommandDelegateData {
     pub twilight_client: TwilightClient,
     pub solus_command_data: Arc<SolusCommandData>,
+    pub generations: Arc<Generations>,
 }
 
 #[async_trait]
@@ -47,7 +126,16 @@ pub trait CommandDelegate {
 #[async_trait]
 impl CommandDelegate for CommandDelegateData {
     fn command_definitions(&self) -> Vec<Command> {
-        [SolusCommand::create_command()].map(std::convert::Into::into).to_vec()
+        [
+            SolusCommand::create_command(),
+            StopCommand::create_command(),
+            HistoryCommand::create_command(),
+            RememberCommand::create_command(),
+            UploadCommand::create_command(),
+            ImagineCommand::create_command(),
+        ]
+            .map(std::convert::Into::into)
+            .to_vec()
     }
 
     async fn handle_interaction(
@@ -79,6 +167,7 @@ impl CommandDelegate for CommandDelegateData {
                 channel,
                 interaction_client: self.twilight_client.interaction(application_id),
                 solus_command_data: self.solus_command_data.clone(),
+                generations: self.generations.clone(),
             };
 
             match command_data.name.as_str() {
@@ -95,6 +184,71 @@ impl CommandDelegate for CommandDelegateData {
                         ).await
                     }
                 }
+                "imagine" => {
+                    if
+                        let Ok(imagine_command) = ImagineCommand::from_interaction(
+                            (*command_data).into()
+                        )
+                    {
+                        imagine_command.handle_command(
+                            command_handler_data,
+                            interaction.id,
+                            &interaction.token
+                        ).await
+                    }
+                }
+                "stop" => {
+                    if
+                        let Ok(stop_command) = StopCommand::from_interaction(
+                            (*command_data).into()
+                        )
+                    {
+                        stop_command.handle_command(
+                            command_handler_data,
+                            interaction.id,
+                            &interaction.token
+                        ).await
+                    }
+                }
+                "history" => {
+                    if
+                        let Ok(history_command) = HistoryCommand::from_interaction(
+                            (*command_data).into()
+                        )
+                    {
+                        history_command.handle_command(
+                            command_handler_data,
+                            interaction.id,
+                            &interaction.token
+                        ).await
+                    }
+                }
+                "remember" => {
+                    if
+                        let Ok(remember_command) = RememberCommand::from_interaction(
+                            (*command_data).into()
+                        )
+                    {
+                        remember_command.handle_command(
+                            command_handler_data,
+                            interaction.id,
+                            &interaction.token
+                        ).await
+                    }
+                }
+                "upload" => {
+                    if
+                        let Ok(upload_command) = UploadCommand::from_interaction(
+                            (*command_data).into()
+                        )
+                    {
+                        upload_command.handle_command(
+                            command_handler_data,
+                            interaction.id,
+                            &interaction.token
+                        ).await
+                    }
+                }
                 &_ => {}
             }
         }
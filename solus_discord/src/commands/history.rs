@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use solus_rust_lib::proto::message::{ history_policy_pb::Policy, HistoryPolicyPb };
+use twilight_interactions::command::{ CommandModel, CommandOption, CreateCommand, CreateOption };
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::http::interaction::{
+    InteractionResponse,
+    InteractionResponseData,
+    InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+
+use super::{ CommandHandler, CommandHandlerData };
+
+#[derive(CommandOption, CreateOption)]
+enum HistoryMode {
+    #[option(name = "All messages", value = "all")]
+    All,
+    #[option(name = "Last messages", value = "last")]
+    Last,
+    #[option(name = "Token budget", value = "tokens")]
+    Tokens,
+    #[option(name = "Summarize older messages", value = "summarize")]
+    Summarize,
+}
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "history", desc = "Choose how much of this channel's history Solus remembers")]
+pub struct HistoryCommand {
+    /// How older messages are handled.
+    mode: HistoryMode,
+    /// Messages to keep, or tokens for the token budget.
+    #[command(min_value = 1)]
+    value: Option<i64>,
+}
+
+impl HistoryCommand {
+    fn history_policy(&self) -> Result<HistoryPolicyPb, String> {
+        let too_large = |_| format!("{} is too large.", self.value.unwrap_or_default());
+        let policy = match (&self.mode, self.value) {
+            (HistoryMode::All, _) => None,
+            (HistoryMode::Last, Some(value)) => {
+                Some(Policy::LastN(u32::try_from(value).map_err(too_large)?))
+            }
+            (HistoryMode::Tokens, Some(value)) => {
+                Some(Policy::TokenBudget(i32::try_from(value).map_err(too_large)?))
+            }
+            (HistoryMode::Summarize, Some(value)) => {
+                Some(Policy::SummarizeKeepLast(u32::try_from(value).map_err(too_large)?))
+            }
+            (_, None) => {
+                return Err("This mode needs a value.".into());
+            }
+        };
+
+        Ok(HistoryPolicyPb { policy })
+    }
+}
+
+#[async_trait]
+impl CommandHandler for HistoryCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str
+    ) {
+        let channel_id = command_handler_data.channel.id.get().to_string();
+        let solus_command_data = command_handler_data.solus_command_data;
+
+        let result = match self.history_policy() {
+            Ok(history_policy) => {
+                // Sessions are keyed by channel, the policy applies to the channel's session.
+                match
+                    solus_rust_lib::get_or_create_session(
+                        solus_command_data.clone(),
+                        channel_id
+                    ).await
+                {
+                    Ok(session_id) =>
+                        solus_rust_lib
+                            ::set_history_policy(solus_command_data, session_id, history_policy).await
+                            .map_err(|e| format!("Failed to update history: {}", e)),
+                    Err(e) => Err(format!("Failed to create session: {}", e)),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        let data = match result {
+            Ok(_) =>
+                InteractionResponseData {
+                    content: Some("History updated.".into()),
+                    ..Default::default()
+                },
+            Err(message) =>
+                InteractionResponseData {
+                    content: Some(message),
+                    flags: Some(MessageFlags::EPHEMERAL),
+                    ..Default::default()
+                },
+        };
+
+        command_handler_data.interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(data),
+                })
+            ).await
+            .ok();
+    }
+}
@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use solus_rust_lib::flux::GENERATE_IMAGE;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+
+use super::solus::{ respond, ChatOptions };
+use super::{ CommandHandler, CommandHandlerData };
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "imagine", desc = "Generate an image")]
+pub struct ImagineCommand {
+    /// What the image should show.
+    prompt: String,
+}
+
+#[async_trait]
+impl CommandHandler for ImagineCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str
+    ) {
+        respond(
+            command_handler_data,
+            interaction_id,
+            interaction_token,
+            &self.prompt,
+            None,
+            ChatOptions {
+                // The model words the image prompt, but it has to call the image tool to do it.
+                forced_function: Some(GENERATE_IMAGE),
+                ..Default::default()
+            }
+        ).await
+    }
+}
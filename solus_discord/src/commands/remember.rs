@@ -0,0 +1,67 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::http::interaction::{
+    InteractionResponse,
+    InteractionResponseData,
+    InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+
+use super::{ CommandHandler, CommandHandlerData };
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "remember", desc = "Tell Solus something to remember in this channel")]
+pub struct RememberCommand {
+    /// What to remember, e.g. "Alex prefers metric units".
+    fact: String,
+}
+
+#[async_trait]
+impl CommandHandler for RememberCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str
+    ) {
+        let channel_id = command_handler_data.channel.id.get().to_string();
+        let solus_command_data = command_handler_data.solus_command_data;
+
+        let result = match
+            solus_rust_lib::get_or_create_session(solus_command_data.clone(), channel_id).await
+        {
+            Ok(session_id) =>
+                solus_rust_lib
+                    ::remember(solus_command_data, session_id, self.fact.clone()).await
+                    .map_err(|e| format!("Failed to remember: {}", e)),
+            Err(e) => Err(format!("Failed to create session: {}", e)),
+        };
+
+        let data = match result {
+            Ok(_) =>
+                InteractionResponseData {
+                    content: Some(format!("I'll remember: {}", self.fact)),
+                    ..Default::default()
+                },
+            Err(message) =>
+                InteractionResponseData {
+                    content: Some(message),
+                    flags: Some(MessageFlags::EPHEMERAL),
+                    ..Default::default()
+                },
+        };
+
+        command_handler_data.interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(data),
+                })
+            ).await
+            .ok();
+    }
+}
@@ -1,16 +1,25 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 use async_trait::async_trait;
-use serde::Deserialize;
-use solus_rust_lib::composer;
+use serde_json::{ Map, Value };
+use solus_rust_lib::composer::{ self, ComposerEvent };
 use solus_rust_lib::data::CommandData as SolusCommandData;
-use solus_rust_lib::gemini::api::{ new_content_pb, new_gemini_request_pb };
+use solus_rust_lib::gemini::api::{
+    enable_code_execution,
+    enable_google_search,
+    force_function_call,
+    new_content_with_blobs_pb,
+    new_gemini_request_pb,
+};
+use solus_rust_lib::history::{ HistorySource, SessionHistory };
+use solus_rust_lib::proto::message::{ GenerationConfigPb, ThinkingConfigPb };
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use twilight_http::client::InteractionClient;
 use twilight_interactions::command::{ CommandModel, CreateCommand };
 use twilight_model::channel::message::Embed;
+use twilight_model::channel::Attachment;
 use twilight_model::http::interaction::{
     InteractionResponse,
     InteractionResponseData,
@@ -20,6 +29,9 @@ use twilight_model::id::marker::InteractionMarker;
 use twilight_model::id::Id;
 use twilight_util::builder::embed::{ EmbedBuilder, ImageSource };
 
+use crate::attachment::blob_from_attachment;
+use crate::failure::failure_message;
+
 use super::{ CommandHandler, CommandHandlerData };
 
 #[derive(CommandModel, CreateCommand)]
@@ -27,19 +39,100 @@ use super::{ CommandHandler, CommandHandlerData };
 pub struct SolusCommand {
     /// Prompt to send to the model.
     prompt: String,
+    /// Randomness of the response, higher is more creative.
+    #[command(min_value = 0.0, max_value = 2.0)]
+    temperature: Option<f64>,
+    /// Nucleus sampling probability mass.
+    #[command(min_value = 0.0, max_value = 1.0)]
+    top_p: Option<f64>,
+    /// Sample from the k most likely tokens.
+    #[command(min_value = 1)]
+    top_k: Option<i64>,
+    /// Maximum number of tokens in the response.
+    #[command(min_value = 1)]
+    max_output_tokens: Option<i64>,
+    /// Image, audio or document for the model to look at.
+    attachment: Option<Attachment>,
+    /// Ground the answer in Google Search results and list its sources.
+    google_search: Option<bool>,
+    /// Let the model write and run Python to work out the answer.
+    code_execution: Option<bool>,
+    /// Tokens the model may think for, 0 turns thinking off and -1 lets the model decide.
+    #[command(min_value = -1, max_value = 32768)]
+    thinking_budget: Option<i64>,
+    /// Show the model's reasoning above the answer.
+    show_thinking: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
+impl SolusCommand {
+    fn generation_config(&self) -> Option<GenerationConfigPb> {
+        if
+            self.temperature.is_none() &&
+            self.top_p.is_none() &&
+            self.top_k.is_none() &&
+            self.max_output_tokens.is_none() &&
+            self.thinking_budget.is_none() &&
+            self.show_thinking.is_none()
+        {
+            return None;
+        }
+
+        let thinking_config = if self.thinking_budget.is_none() && self.show_thinking.is_none() {
+            None
+        } else {
+            Some(ThinkingConfigPb {
+                thinking_budget: self.thinking_budget.map(|thinking_budget| thinking_budget as i32),
+                include_thoughts: self.show_thinking.unwrap_or_default(),
+            })
+        };
+
+        Some(GenerationConfigPb {
+            temperature: self.temperature.map(|temperature| temperature as f32),
+            top_p: self.top_p.map(|top_p| top_p as f32),
+            top_k: self.top_k.map(|top_k| top_k as i32),
+            max_output_tokens: self.max_output_tokens.map(|max_output_tokens| {
+                max_output_tokens as i32
+            }),
+            thinking_config,
+            ..Default::default()
+        })
+    }
+}
+
+/// How `respond` builds the request, on top of the prompt and attachment.
+#[derive(Default)]
+pub(super) struct ChatOptions {
+    pub generation_config: Option<GenerationConfigPb>,
+    /// A function the model has to call, see `force_function_call`.
+    pub forced_function: Option<&'static str>,
+    pub google_search: bool,
+    pub code_execution: bool,
+}
+
+#[derive(Debug)]
 struct EmbedEntry {
     text: Option<String>,
     image: Option<String>,
     function_call: Option<EmbedFunctionCall>,
+    code_execution: Option<EmbedCodeExecution>,
+    thought: Option<String>,
+    blocked: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 struct EmbedFunctionCall {
     name: String,
-    args: HashMap<String, String>,
+    args: Map<String, Value>,
+    // Set when the tool failed, the model is told and answers anyway.
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct EmbedCodeExecution {
+    language: String,
+    code: String,
+    // Outcome and output, once the code has run.
+    result: Option<(String, String)>,
 }
 
 struct ChatError {
@@ -54,75 +147,126 @@ impl CommandHandler for SolusCommand {
         interaction_id: Id<InteractionMarker>,
         interaction_token: &'_ str
     ) {
-        let prompt = &self.prompt;
-        let interaction_client = command_handler_data.interaction_client;
-        let solus_command_data = command_handler_data.solus_command_data;
-        let channel_id = command_handler_data.channel.id.get().to_string();
+        respond(
+            command_handler_data,
+            interaction_id,
+            interaction_token,
+            &self.prompt,
+            self.attachment.as_ref(),
+            ChatOptions {
+                generation_config: self.generation_config(),
+                google_search: self.google_search.unwrap_or_default(),
+                code_execution: self.code_execution.unwrap_or_default(),
+                ..Default::default()
+            }
+        ).await
+    }
+}
 
-        interaction_client
-            .create_response(
-                interaction_id,
-                interaction_token,
-                &(InteractionResponse {
-                    kind: InteractionResponseType::ChannelMessageWithSource,
-                    data: Some(InteractionResponseData {
-                        embeds: Some(vec![prompt_embed(prompt)]),
-                        ..Default::default()
-                    }),
-                })
-            ).await
-            .ok();
+/// Answers `prompt` in the interaction's channel session, streaming the answer into embeds.
+pub(super) async fn respond(
+    command_handler_data: CommandHandlerData<'_>,
+    interaction_id: Id<InteractionMarker>,
+    interaction_token: &'_ str,
+    prompt: &str,
+    attachment: Option<&Attachment>,
+    chat_options: ChatOptions
+) {
+    let interaction_client = command_handler_data.interaction_client;
+    let solus_command_data = command_handler_data.solus_command_data;
+    let channel_id = command_handler_data.channel.id.get().to_string();
+    // /stop cancels every generation running in the channel, this one until it returns.
+    let generation = command_handler_data.generations.start(channel_id.clone());
 
-        match
-            chat(
-                prompt,
-                channel_id,
-                solus_command_data,
-                &interaction_client,
-                interaction_token
-            ).await
-        {
-            Ok(_) => {
-                return;
-            }
-            Err(e) => {
-                let _ = interaction_client
-                    .update_response(interaction_token)
-                    .embeds(
-                        Some(
-                            &[
-                                prompt_embed(prompt),
-                                EmbedBuilder::new()
-                                    .title("Failed")
-                                    .color(0xe53935)
-                                    .description(format!("```\n{}\n```", e.message))
-                                    .build(),
-                            ]
-                        )
+    interaction_client
+        .create_response(
+            interaction_id,
+            interaction_token,
+            &(InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseData {
+                    embeds: Some(vec![prompt_embed(prompt)]),
+                    ..Default::default()
+                }),
+            })
+        ).await
+        .ok();
+
+    match
+        chat(
+            prompt,
+            attachment,
+            chat_options,
+            channel_id,
+            generation.cancellation_token(),
+            solus_command_data,
+            &interaction_client,
+            interaction_token
+        ).await
+    {
+        Ok(_) => {
+            return;
+        }
+        Err(e) => {
+            let _ = interaction_client
+                .update_response(interaction_token)
+                .embeds(
+                    Some(
+                        &[
+                            prompt_embed(prompt),
+                            EmbedBuilder::new()
+                                .title("Failed")
+                                .color(0xe53935)
+                                .description(format!("```\n{}\n```", e.message))
+                                .build(),
+                        ]
                     )
-                    .unwrap().await
-                    .ok();
-            }
+                )
+                .unwrap().await
+                .ok();
         }
     }
 }
 
 async fn chat(
     prompt: &str,
+    attachment: Option<&Attachment>,
+    chat_options: ChatOptions,
     channel_id: String,
+    cancellation_token: CancellationToken,
     solus_command_data: Arc<SolusCommandData>,
     interaction_client: &InteractionClient<'_>,
     interaction_token: &'_ str
 ) -> Result<(), ChatError> {
-    let content = new_content_pb("user".into(), prompt.into());
-    let gemini_request = new_gemini_request_pb(vec![content]);
+    let mut blobs = vec![];
+    if let Some(attachment) = attachment {
+        let blob = blob_from_attachment(&solus_command_data.reqwest_client, attachment).await.map_err(
+            |e| ChatError {
+                message: format!("Failed to download attachment: {}", e),
+            }
+        )?;
+        blobs.push(blob);
+    }
+
+    let content = new_content_with_blobs_pb("user".into(), prompt.into(), blobs);
+    let mut gemini_request = new_gemini_request_pb(vec![content]);
+    gemini_request.generation_config = chat_options.generation_config;
+    if let Some(function_name) = chat_options.forced_function {
+        force_function_call(&mut gemini_request, function_name);
+    }
+    if chat_options.google_search {
+        enable_google_search(&mut gemini_request);
+    }
+    if chat_options.code_execution {
+        enable_code_execution(&mut gemini_request);
+    }
 
     let (outer_tx, outer_rx) = mpsc::unbounded_channel(); // Create a bounded channel
 
-    let session_id = match
+    let history: Arc<dyn HistorySource> = match
         solus_rust_lib::get_or_create_session(solus_command_data.clone(), channel_id).await
     {
-        Ok(session_id) => Arc::new(session_id),
+        Ok(session_id) => Arc::new(SessionHistory::new(session_id)),
         Err(e) => {
             return Err(ChatError {
                 message: format!("Failed to create session: {}", e),
@@ -131,84 +275,169 @@ async fn chat(
     };
 
     let handle = tokio::spawn(async move { composer
-            ::invoker(solus_command_data.clone(), session_id, gemini_request, outer_tx).await
+            ::invoker(
+                solus_command_data.clone(),
+                history,
+                gemini_request,
+                outer_tx,
+                cancellation_token
+            ).await
             .map_err(|e| ChatError {
-                message: format!("Invocation on thread failed: {}", e),
+                message: failure_message(&e),
             }) });
 
     let mut outer_receiver = UnboundedReceiverStream::new(outer_rx);
 
     let mut entries: Vec<EmbedEntry> = vec![];
+    // Title and link of each web source, in the order the model numbers them.
+    let mut sources: Vec<(String, String)> = vec![];
+    let mut stopped = false;
 
-    while let Some(message) = outer_receiver.next().await {
-        println!("{:?}", message);
-        let parts = match message.candidates[0].content.as_ref() {
-            Some(content) => &content.parts,
-            None => {
-                continue;
+    while let Some(event) = outer_receiver.next().await {
+        println!("{:?}", event);
+        match event {
+            ComposerEvent::TextDelta(text) => {
+                match entries.last_mut() {
+                    Some(EmbedEntry { text: Some(last_text), .. }) => {
+                        last_text.push_str(&text);
+                    }
+                    _ => {
+                        // No entries yet, or the previous entry had no text
+                        entries.push(EmbedEntry {
+                            text: Some(text),
+                            image: None,
+                            function_call: None,
+                            code_execution: None,
+                            thought: None,
+                            blocked: None,
+                        });
+                    }
+                }
             }
-        };
-
-        for part in parts {
-            if let Some(text) = &part.text {
-                if !text.is_empty() {
-                    if let Some(last_entry) = entries.last_mut() {
-                        if let Some(last_text) = &mut last_entry.text {
-                            last_text.push_str(text);
-                        } else {
-                            // Previous entry had no text, so add a new one
-                            entries.push(EmbedEntry {
-                                text: Some(text.clone()),
-                                image: None,
-                                function_call: None,
-                            });
-                        }
-                    } else {
-                        // No entries yet, add the first one
+            ComposerEvent::ThoughtDelta(text) => {
+                match entries.last_mut() {
+                    Some(EmbedEntry { thought: Some(last_thought), .. }) => {
+                        last_thought.push_str(&text);
+                    }
+                    _ => {
                         entries.push(EmbedEntry {
-                            text: part.text.clone(),
+                            text: None,
                             image: None,
                             function_call: None,
+                            code_execution: None,
+                            thought: Some(text),
+                            blocked: None,
                         });
                     }
-                } else {
-                    println!("Solus: empty text part!");
                 }
-            } else if let Some(function_call) = &part.function_call {
-                let function_name = &function_call.name;
-                let function_args = &function_call.args;
+            }
+            ComposerEvent::ToolCallStarted(function_call) => {
                 entries.push(EmbedEntry {
                     text: None,
                     image: None,
                     function_call: Some(EmbedFunctionCall {
-                        name: function_name.to_string(),
-                        args: function_args.clone(),
+                        name: function_call.name,
+                        args: function_call.args().unwrap_or_default(),
+                        error: None,
                     }),
+                    code_execution: None,
+                    thought: None,
+                    blocked: None,
                 });
-            } else if let Some(function_response) = &part.function_response {
-                match function_response.name.as_str() {
-                    "generate_image" => {
-                        let image_url = &function_response.response;
-                        entries.push(EmbedEntry {
-                            text: None,
-                            image: Some(image_url.clone()),
-                            function_call: None,
-                        });
-                    }
-                    _ => {
-                        // Handle
-                    }
-                }
-            } else {
-                return Err(ChatError {
-                    message: "Part has no text, function_call or function_response".into(),
+            }
+            ComposerEvent::ImageGenerated { url } => {
+                entries.push(EmbedEntry {
+                    text: None,
+                    image: Some(url),
+                    function_call: None,
+                    code_execution: None,
+                    thought: None,
+                    blocked: None,
                 });
             }
+            ComposerEvent::Blocked { reason, safety_ratings } => {
+                let categories = safety_ratings
+                    .iter()
+                    .filter(|safety_rating| safety_rating.blocked)
+                    .map(|safety_rating| safety_rating.category.as_str())
+                    .collect::<Vec<&str>>();
+                entries.push(EmbedEntry {
+                    text: None,
+                    image: None,
+                    function_call: None,
+                    code_execution: None,
+                    thought: None,
+                    blocked: Some(
+                        if categories.is_empty() {
+                            reason
+                        } else {
+                            format!("{} ({})", reason, categories.join(", "))
+                        }
+                    ),
+                });
+            }
+            ComposerEvent::CodeExecutionStarted(executable_code) => {
+                entries.push(EmbedEntry {
+                    text: None,
+                    image: None,
+                    function_call: None,
+                    code_execution: Some(EmbedCodeExecution {
+                        language: executable_code.language.to_lowercase(),
+                        code: executable_code.code,
+                        result: None,
+                    }),
+                    thought: None,
+                    blocked: None,
+                });
+            }
+            ComposerEvent::CodeExecutionFinished(code_execution_result) => {
+                // The result belongs to the code the model sent right before it.
+                let code_execution = entries
+                    .iter_mut()
+                    .rev()
+                    .find_map(|entry| entry.code_execution.as_mut());
+                if let Some(code_execution) = code_execution {
+                    code_execution.result = Some((
+                        code_execution_result.outcome,
+                        code_execution_result.output,
+                    ));
+                }
+            }
+            ComposerEvent::ToolCallFailed { name, error } => {
+                // The error belongs to the latest call of that tool.
+                let function_call = entries
+                    .iter_mut()
+                    .rev()
+                    .filter_map(|entry| entry.function_call.as_mut())
+                    .find(|function_call| function_call.name == name);
+                if let Some(function_call) = function_call {
+                    function_call.error = Some(error);
+                }
+            }
+            ComposerEvent::Grounded(grounding_metadata) => {
+                sources = grounding_metadata.grounding_chunks
+                    .into_iter()
+                    .filter_map(|grounding_chunk| grounding_chunk.web)
+                    .map(|web| (web.title, web.uri))
+                    .collect();
+            }
+            ComposerEvent::Cancelled => {
+                stopped = true;
+            }
+            _ => {
+                continue;
+            }
         }
 
         let mut embeds = entries_to_embed(&entries);
         // add prompt_embed to the beginning
         embeds.insert(0, prompt_embed(prompt));
+        if !sources.is_empty() {
+            embeds.push(sources_embed(&sources));
+        }
+        if stopped {
+            embeds.push(stopped_embed());
+        }
 
         interaction_client
             .update_response(interaction_token)
@@ -231,23 +460,92 @@ fn response_embed(prompt: &str) -> Embed {
 }
 
 fn function_call_embed(function_call: &EmbedFunctionCall) -> Embed {
+    let mut description = format!(
+        "```{}({})```",
+        function_call.name,
+        function_call.args
+            .iter()
+            .map(|(l, r)| format!("{}={}", l, r))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    let mut color = 0x18a999;
+    if let Some(error) = &function_call.error {
+        description.push_str(&format!("\nFailed: `{}`", error));
+        color = 0xe53935;
+    }
+
+    EmbedBuilder::new().title("Function Call").color(color).description(description).build()
+}
+
+fn code_execution_embed(code_execution: &EmbedCodeExecution) -> Embed {
+    let mut description = format!(
+        "```{}\n{}\n```",
+        code_execution.language,
+        code_execution.code.trim_end()
+    );
+    let mut color = 0x18a999;
+    if let Some((outcome, output)) = &code_execution.result {
+        if outcome != "OUTCOME_OK" {
+            description.push_str(&format!("\n`{}`", outcome));
+            color = 0xe53935;
+        }
+        if !output.trim().is_empty() {
+            description.push_str(&format!("\n```\n{}\n```", output.trim_end()));
+        }
+    }
+
+    EmbedBuilder::new().title("Code Execution").color(color).description(description).build()
+}
+
+// Discord caps embed descriptions at 4096 characters, reasoning easily runs longer.
+const MAX_REASONING_CHARS: usize = 4000;
+
+fn reasoning_embed(thought: &str) -> Embed {
+    let mut reasoning: String = thought.trim().chars().take(MAX_REASONING_CHARS).collect();
+    if reasoning.len() < thought.trim().len() {
+        reasoning.push('…');
+    }
+
+    // Spoilered, so the reasoning stays collapsed until someone clicks it.
+    EmbedBuilder::new()
+        .title("Reasoning")
+        .color(0x9e9e9e)
+        .description(format!("||{}||", reasoning))
+        .build()
+}
+
+fn blocked_embed(reason: &str) -> Embed {
+    EmbedBuilder::new()
+        .title("Blocked")
+        .color(0xe53935)
+        .description(format!("The response was blocked: `{}`", reason))
+        .build()
+}
+
+fn sources_embed(sources: &[(String, String)]) -> Embed {
     EmbedBuilder::new()
-        .title("Function Call")
-        .color(0x18a999)
+        .title("Sources")
+        .color(0x4285f4)
         .description(
-            format!(
-                "```{}({})```",
-                function_call.name,
-                function_call.args
-                    .iter()
-                    .map(|(l, r)| format!("{}=\"{}\"", l, r))
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            )
+            sources
+                .iter()
+                .enumerate()
+                .map(|(index, (title, uri))| format!("{}. [{}]({})", index + 1, title, uri))
+                .collect::<Vec<String>>()
+                .join("\n")
         )
         .build()
 }
 
+fn stopped_embed() -> Embed {
+    EmbedBuilder::new()
+        .title("Stopped")
+        .color(0xe53935)
+        .description("The answer was stopped.")
+        .build()
+}
+
 fn image_embed(image_url: &str) -> Embed {
     let mut builder = EmbedBuilder::new().title("Function Response").color(0x109648);
     let image_source = ImageSource::url(image_url);
@@ -272,6 +570,12 @@ fn entries_to_embed(entries: &Vec<EmbedEntry>) -> Vec<Embed> {
                 Some(image_embed(image_url))
             } else if let Some(function_call) = &entry.function_call {
                 Some(function_call_embed(function_call))
+            } else if let Some(code_execution) = &entry.code_execution {
+                Some(code_execution_embed(code_execution))
+            } else if let Some(thought) = &entry.thought {
+                Some(reasoning_embed(thought))
+            } else if let Some(reason) = &entry.blocked {
+                Some(blocked_embed(reason))
             } else {
                 None
             }
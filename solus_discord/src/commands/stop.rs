@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::http::interaction::{
+    InteractionResponse,
+    InteractionResponseData,
+    InteractionResponseType,
+};
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+
+use super::{ CommandHandler, CommandHandlerData };
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "stop", desc = "Stop the answers being generated in this channel")]
+pub struct StopCommand {}
+
+#[async_trait]
+impl CommandHandler for StopCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str
+    ) {
+        let channel_id = command_handler_data.channel.id.get().to_string();
+
+        let data = if command_handler_data.generations.stop(&channel_id) {
+            InteractionResponseData {
+                content: Some("Stopped.".into()),
+                ..Default::default()
+            }
+        } else {
+            InteractionResponseData {
+                content: Some("Nothing is running in this channel.".into()),
+                flags: Some(MessageFlags::EPHEMERAL),
+                ..Default::default()
+            }
+        };
+
+        command_handler_data.interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(data),
+                })
+            ).await
+            .ok();
+    }
+}
@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use twilight_interactions::command::{ CommandModel, CreateCommand };
+use twilight_model::channel::Attachment;
+use twilight_model::http::interaction::{ InteractionResponse, InteractionResponseType };
+use twilight_model::id::marker::InteractionMarker;
+use twilight_model::id::Id;
+
+use crate::attachment::download_attachment;
+
+use super::{ CommandHandler, CommandHandlerData };
+
+#[derive(CommandModel, CreateCommand)]
+#[command(name = "upload", desc = "Add a document Solus can search and cite")]
+pub struct UploadCommand {
+    /// Markdown, text or PDF file.
+    document: Attachment,
+}
+
+#[async_trait]
+impl CommandHandler for UploadCommand {
+    async fn handle_command(
+        &self,
+        command_handler_data: CommandHandlerData<'_>,
+        interaction_id: Id<InteractionMarker>,
+        interaction_token: &'_ str
+    ) {
+        let channel_id = command_handler_data.channel.id;
+        let interaction_client = command_handler_data.interaction_client;
+        let solus_command_data = command_handler_data.solus_command_data;
+
+        // Embedding a long document takes longer than Discord waits for a response.
+        interaction_client
+            .create_response(
+                interaction_id,
+                interaction_token,
+                &(InteractionResponse {
+                    kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                    data: None,
+                })
+            ).await
+            .ok();
+
+        let filename = &self.document.filename;
+        // Sources are global, a file only replaces the one with its name in the same channel.
+        let source = format!("discord/{}/{}", channel_id, filename);
+        let message = match
+            download_attachment(&solus_command_data.reqwest_client, &self.document).await
+        {
+            Ok(bytes) =>
+                match
+                    solus_rust_lib::ingest_document(
+                        solus_command_data,
+                        source,
+                        bytes
+                    ).await
+                {
+                    Ok(chunks) => format!("Added `{}` ({} chunks).", filename, chunks),
+                    Err(e) => format!("Failed to add `{}`: {}", filename, e),
+                }
+            Err(e) => format!("Failed to download `{}`: {}", filename, e),
+        };
+
+        interaction_client
+            .update_response(interaction_token)
+            .content(Some(&message))
+            .unwrap().await
+            .ok();
+    }
+}
@@ -0,0 +1,15 @@
+use solus_rust_lib::gemini::error::GeminiError;
+
+/// What to tell the user when a model call fails.
+pub fn failure_message(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<GeminiError>() {
+        Some(GeminiError::RateLimited { .. }) => {
+            "I'm getting too many requests right now, try again in a minute.".into()
+        }
+        Some(GeminiError::Unavailable { .. }) | Some(GeminiError::Transport(_)) => {
+            "Gemini is unavailable right now, try again later.".into()
+        }
+        Some(gemini_error) => gemini_error.to_string(),
+        None => format!("Invocation on thread failed: {}", e),
+    }
+}
@@ -4,11 +4,10 @@ use commands::CommandDelegate;
 use dotenv::dotenv;
 use futures::stream::StreamExt;
 use solus_rust_lib::{
+    composer::{self, ComposerEvent},
     data::{self, get_or_create_session, CommandData as SolusCommandData},
-    gemini::{
-        self,
-        api::{new_content_pb, new_gemini_request_pb},
-    },
+    gemini::api::{disable_function_calls, new_content_with_blobs_pb, new_gemini_request_pb},
+    llm, tools,
 };
 use std::{env, error::Error, sync::Arc, time::Duration};
 use tokio::sync::{mpsc, Mutex};
@@ -25,7 +24,10 @@ use twilight_model::{
 };
 
 mod activity;
+mod attachment;
+mod channel_history;
 mod commands;
+mod failure;
 
 extern crate solus_rust_lib;
 
@@ -91,16 +93,18 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let reqwest_client = solus_rust_lib::get_client();
 
     let solus_command_data = Arc::new(SolusCommandData {
+        llm_provider: llm::provider_from_env(&reqwest_client)?,
         reqwest_client,
         connection: Mutex::new(connection),
         replicate_token: env::var("REPLICATE_TOKEN").expect("REPLICATE_TOKEN must be set."),
-        gemini_token: env::var("GEMINI_TOKEN").expect("GEMINI_TOKEN must be set."),
         brave_token: env::var("BRAVE_TOKEN").expect("BRAVE_TOKEN must be set."),
+        tool_registry: tools::default_registry(),
     });
 
     let command_data = Arc::new(CommandDelegateData {
         solus_command_data: solus_command_data.clone(),
         twilight_client: HttpClient::new(token),
+        generations: Default::default(),
     });
 
     let application_id = command_data
@@ -170,33 +174,39 @@ async fn handle_event(
             return Ok(());
         }
 
-        let messages = command_data
-            .twilight_client
-            .channel_messages(message.channel_id)
-            .before(message.id)
-            .limit(10)?
-            .await?
-            .model()
-            .await?;
+        let channel_id = message.channel_id.get().to_string();
+        let session_id =
+            get_or_create_session(&command_data.solus_command_data, channel_id.clone()).await?;
+        let history = channel_history::fetch(
+            &command_data.twilight_client,
+            message.channel_id,
+            message.id,
+            session_id,
+        )
+        .await?;
 
-        let mut contents = vec![];
-        messages.iter().rev().for_each(|m| {
-            let content = m.content.clone();
-            if m.author.bot {
-                contents.push(new_content_pb("model".into(), content));
-            } else {
-                contents.push(new_content_pb(
-                    "user".into(),
-                    format!("USER {}: \"{}\"", m.author.name, content),
-                ));
+        let mut blobs = vec![];
+        for message_attachment in &message.attachments {
+            match attachment::blob_from_attachment(
+                &command_data.solus_command_data.reqwest_client,
+                message_attachment,
+            )
+            .await
+            {
+                Ok(blob) => blobs.push(blob),
+                Err(e) => println!("Skipping attachment: {}", e),
             }
-        });
-        contents.push(new_content_pb(
-            "user".into(),
-            format!("USER {}: \"{}\"", message.author.name, message.content),
-        ));
+        }
+        let content =
+            new_content_with_blobs_pb("user".into(), channel_history::user_text(&message), blobs);
+
+        let mut gemini_request = new_gemini_request_pb(vec![content]);
+        // Mentions are casual chat, they never run tools.
+        disable_function_calls(&mut gemini_request);
 
-        let gemini_request = new_gemini_request_pb(contents);
+        // /stop in the channel stops mentions too.
+        let generation = command_data.generations.start(channel_id);
+        let cancellation_token = generation.cancellation_token();
 
         let (outer_tx, outer_rx) = mpsc::unbounded_channel();
         let mut outer_receiver = UnboundedReceiverStream::new(outer_rx);
@@ -212,27 +222,29 @@ async fn handle_event(
         let solus_command_data = command_data.solus_command_data.clone();
 
         let handle = tokio::spawn(async move {
-            gemini::invoke_simple(solus_command_data, &gemini_request, outer_tx)
-                .await
-                .map_err(|e| println!("Invocation on thread failed: {}", e))
+            composer::invoker(
+                solus_command_data,
+                Arc::new(history),
+                gemini_request,
+                outer_tx,
+                cancellation_token,
+            )
+            .await
         });
 
         let mut response_text = String::new();
-        while let Some(gemini_response) = outer_receiver.next().await {
-            let parts = match gemini_response.candidates[0].content.as_ref() {
-                Some(content) => &content.parts,
-                None => {
-                    continue;
+        while let Some(event) = outer_receiver.next().await {
+            match event {
+                ComposerEvent::TextDelta(text) => response_text.push_str(&text),
+                ComposerEvent::Blocked { reason, .. } => {
+                    response_text = format!("I can't respond to that (blocked: {}).", reason);
                 }
-            };
-
-            for part in parts {
-                if let Some(text) = &part.text {
-                    if !text.is_empty() {
-                        response_text.push_str(text);
-                    } else {
-                        println!("Solus: empty text part!");
-                    }
+                // The turn goes on without the tool, note the failure before the answer.
+                ComposerEvent::ToolCallFailed { name, error } => {
+                    response_text.push_str(&format!("*`{}` failed: {}*\n", name, error));
+                }
+                _ => {
+                    continue;
                 }
             }
 
@@ -243,9 +255,21 @@ async fn handle_event(
                 .await?;
         }
 
-        let _ = handle
-            .await
-            .map_err(|e| println!("Invocation on thread failed: {}", e));
+        match handle.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                println!("Invocation on thread failed: {}", e);
+                // Keep whatever was streamed before the failure.
+                if response_text.is_empty() {
+                    command_data
+                        .twilight_client
+                        .update_message(response.channel_id, response.id)
+                        .content(Some(&failure::failure_message(&e)))?
+                        .await?;
+                }
+            }
+            Err(e) => println!("Invocation on thread failed: {}", e),
+        }
     }
 
     Ok(())
@@ -1,43 +1,243 @@
-mod data;
-mod gemini;
-mod composer;
-mod flux;
-mod proto;
-mod brave;
-
 use anyhow::{ bail, Result };
-use data::CommandData;
 use dotenv::dotenv;
-use gemini::api::{ new_content_pb, new_gemini_request_pb };
-use rusqlite::Connection;
+use solus_rust_lib::{
+    composer::{ self, ComposerEvent },
+    data::{ self, CommandData },
+    documents,
+    gemini::api::{
+        enable_code_execution,
+        enable_google_search,
+        new_content_with_blobs_pb,
+        new_gemini_request_pb,
+    },
+    history::{ HistorySource, SessionHistory },
+    llm,
+    memory,
+    proto::message::{
+        history_policy_pb::Policy,
+        BlobPb,
+        GenerationConfigPb,
+        HistoryPolicyPb,
+        SafetySettingPb,
+        ThinkingConfigPb,
+    },
+    tools,
+};
 use tokio::sync::{ mpsc, Mutex };
-use std::{ env, io, sync::Arc };
+use std::{ env, fs, io::{ self, Write }, path::Path, sync::Arc, time::Duration };
 use tokio_stream::{ wrappers::UnboundedReceiverStream, StreamExt };
+use tokio_util::sync::CancellationToken;
+
+#[derive(Default)]
+struct CliOptions {
+    generation_config: Option<GenerationConfigPb>,
+    safety_settings: Vec<SafetySettingPb>,
+    history_policy: Option<HistoryPolicyPb>,
+    google_search: bool,
+    code_execution: bool,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<CliOptions> {
+    let mut options = CliOptions::default();
+    let mut args = args;
+
+    while let Some(arg) = args.next() {
+        let mut value = || match args.next() {
+            Some(value) => Ok(value),
+            None => bail!("{} expects a value", arg),
+        };
+
+        match arg.as_str() {
+            "--temperature" => {
+                generation_config(&mut options).temperature = Some(value()?.parse()?);
+            }
+            "--top-p" => {
+                generation_config(&mut options).top_p = Some(value()?.parse()?);
+            }
+            "--top-k" => {
+                generation_config(&mut options).top_k = Some(value()?.parse()?);
+            }
+            "--max-output-tokens" => {
+                generation_config(&mut options).max_output_tokens = Some(value()?.parse()?);
+            }
+            "--stop" => {
+                let stop_sequence = value()?;
+                generation_config(&mut options).stop_sequences.push(stop_sequence);
+            }
+            "--safety-setting" => {
+                // e.g. HARM_CATEGORY_HARASSMENT=BLOCK_ONLY_HIGH
+                let safety_setting = value()?;
+                match safety_setting.split_once('=') {
+                    Some((category, threshold)) => {
+                        options.safety_settings.push(SafetySettingPb {
+                            category: category.into(),
+                            threshold: threshold.into(),
+                        });
+                    }
+                    None => bail!("--safety-setting expects CATEGORY=THRESHOLD"),
+                }
+            }
+            "--history" => {
+                options.history_policy = parse_history_policy(&value()?)?;
+            }
+            "--thinking-budget" => {
+                thinking_config(&mut options).thinking_budget = Some(value()?.parse()?);
+            }
+            "--show-thinking" => {
+                thinking_config(&mut options).include_thoughts = true;
+            }
+            "--google-search" => {
+                options.google_search = true;
+            }
+            "--code-execution" => {
+                options.code_execution = true;
+            }
+            _ => bail!("Unknown argument: {}", arg),
+        }
+    }
+
+    Ok(options)
+}
+
+// all, last:N, tokens:N or summarize:N
+fn parse_history_policy(history: &str) -> Result<Option<HistoryPolicyPb>> {
+    let policy = match history.split_once(':') {
+        None if history == "all" => None,
+        Some(("last", last_n)) => Some(Policy::LastN(last_n.parse()?)),
+        Some(("tokens", token_budget)) => Some(Policy::TokenBudget(token_budget.parse()?)),
+        Some(("summarize", keep_last)) => Some(Policy::SummarizeKeepLast(keep_last.parse()?)),
+        _ => bail!("--history expects all, last:N, tokens:N or summarize:N"),
+    };
+
+    Ok(policy.map(|policy| HistoryPolicyPb { policy: Some(policy) }))
+}
+
+fn generation_config(options: &mut CliOptions) -> &mut GenerationConfigPb {
+    options.generation_config.get_or_insert_with(GenerationConfigPb::default)
+}
+
+fn thinking_config(options: &mut CliOptions) -> &mut ThinkingConfigPb {
+    generation_config(options).thinking_config.get_or_insert_with(ThinkingConfigPb::default)
+}
+
+fn mime_type_from_path(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        Some("pdf") => "application/pdf",
+        Some("mp3") => "audio/mp3",
+        Some("wav") => "audio/wav",
+        Some("ogg") => "audio/ogg",
+        Some("txt" | "md") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn manage_caches(command_data: &CommandData, cache_args: &[String]) -> Result<()> {
+    let provider = &command_data.llm_provider;
+    let cache_args = cache_args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    match cache_args.as_slice() {
+        ["list"] => {
+            for cache in provider.list_caches().await? {
+                println!(
+                    "{} {} {} tokens, expires {}",
+                    cache.name,
+                    cache.model,
+                    cache.total_token_count,
+                    cache.expire_time
+                );
+            }
+        }
+        ["extend", name, seconds] => {
+            let cache = provider.update_cache_ttl(name, Duration::from_secs(seconds.parse()?)).await?;
+            println!("{} expires {}", cache.name, cache.expire_time);
+        }
+        ["delete", name] => {
+            provider.delete_cache(name).await?;
+            println!("Deleted {}", name);
+        }
+        _ => bail!("cache expects list, extend <name> <seconds> or delete <name>"),
+    }
+
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
 
-    let connection = match Connection::open_in_memory() {
-        Ok(conn) => {
-            println!("Database connection established.");
-            conn
-        }
-        Err(e) => {
-            panic!("Database connection FAILED! {}", e);
-        }
-    };
+    let mut args = env::args().skip(1).peekable();
+    // `ingest <files...>` adds documents to the bot's database instead of starting a chat.
+    let ingest_paths = args
+        .next_if(|arg| arg == "ingest")
+        .map(|_| args.by_ref().collect::<Vec<String>>());
+    // `cache list`, `cache extend <name> <seconds>` or `cache delete <name>` manage Gemini's
+    // context caches.
+    let cache_args = args
+        .next_if(|arg| arg == "cache")
+        .map(|_| args.by_ref().collect::<Vec<String>>());
+    let options = parse_args(args)?;
+
+    // Chat reads the same database `ingest` writes to, so search_documents finds its chunks.
+    let connection = solus_rust_lib::get_connection();
+
+    let reqwest_client = reqwest::Client::new();
 
     let command_data = Arc::new(CommandData {
-        reqwest_client: reqwest::Client::new(),
+        llm_provider: llm::provider_from_env(&reqwest_client)?,
+        reqwest_client,
         connection: Mutex::new(connection),
         replicate_token: env::var("REPLICATE_TOKEN").expect("REPLICATE_TOKEN must be set."),
-        gemini_token: env::var("GEMINI_TOKEN").expect("GEMINI_TOKEN must be set."),
         brave_token: env::var("BRAVE_TOKEN").expect("BRAVE_TOKEN must be set."),
+        tool_registry: tools::default_registry(),
     });
 
     data::setup(&command_data).await?;
-    let session_id = Arc::new(data::create_session(&command_data).await?);
+
+    if let Some(ingest_paths) = ingest_paths {
+        if ingest_paths.is_empty() {
+            bail!("ingest expects Markdown, text or PDF files");
+        }
+        for path in ingest_paths {
+            match documents::ingest_file(&command_data, Path::new(&path)).await {
+                Ok(chunks) => println!("Ingested {} ({} chunks)", path, chunks),
+                Err(e) => println!("Failed to ingest {}: {}", path, e),
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(cache_args) = cache_args {
+        return manage_caches(&command_data, &cache_args).await;
+    }
+
+    let session_id = data::create_session(&command_data).await?;
+    if let Some(history_policy) = &options.history_policy {
+        data::set_history_policy(&command_data, &session_id, history_policy).await?;
+    }
+    let history: Arc<dyn HistorySource> = Arc::new(SessionHistory::new(session_id.as_str()));
+
+    let mut attachments = vec![];
+
+    // Ctrl-C stops the running turn, or exits when there is none.
+    let running_turn: Arc<std::sync::Mutex<Option<CancellationToken>>> = Default::default();
+    let running_turn_clone = running_turn.clone();
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            match running_turn_clone.lock().unwrap().take() {
+                Some(cancellation_token) => cancellation_token.cancel(),
+                None => std::process::exit(130),
+            }
+        }
+    });
 
     loop {
         // Get user input
@@ -50,20 +250,58 @@ async fn main() -> Result<()> {
             return Ok(());
         }
 
-        let content = new_content_pb("user".into(), input.into());
-        let gemini_request = new_gemini_request_pb(vec![content]);
+        // Attachments are queued and sent along with the next prompt.
+        if let Some(path) = input.strip_prefix("/attach ") {
+            match fs::read(path.trim()) {
+                Ok(data) => {
+                    attachments.push(BlobPb {
+                        mime_type: mime_type_from_path(path.trim()).into(),
+                        data,
+                    });
+                    println!("Attached {}", path.trim());
+                }
+                Err(e) => println!("Failed to attach {}: {}", path.trim(), e),
+            }
+            continue;
+        }
+
+        if let Some(fact) = input.strip_prefix("/remember ") {
+            match memory::remember(&command_data, &session_id, fact.trim()).await {
+                Ok(()) => println!("Remembered."),
+                Err(e) => println!("Failed to remember: {}", e),
+            }
+            continue;
+        }
+
+        let content = new_content_with_blobs_pb(
+            "user".into(),
+            input.into(),
+            std::mem::take(&mut attachments)
+        );
+        let mut gemini_request = new_gemini_request_pb(vec![content]);
+        gemini_request.generation_config = options.generation_config.clone();
+        gemini_request.safety_settings = options.safety_settings.clone();
+        if options.google_search {
+            enable_google_search(&mut gemini_request);
+        }
+        if options.code_execution {
+            enable_code_execution(&mut gemini_request);
+        }
 
         let (outer_tx, outer_rx) = mpsc::unbounded_channel(); // Create a bounded channel
 
         let command_data_clone = command_data.clone();
-        let session_id = session_id.clone();
+        let history = history.clone();
+        let cancellation_token = CancellationToken::new();
+        *running_turn.lock().unwrap() = Some(cancellation_token.clone());
 
         let handle = tokio::spawn(async move {
             let e = composer::invoker(
                 command_data_clone,
-                session_id,
+                history,
                 gemini_request,
-                outer_tx
+                outer_tx,
+                cancellation_token
             ).await;
 
             if e.is_err() {
@@ -72,11 +310,61 @@ async fn main() -> Result<()> {
         });
 
         let mut outer_receiver = UnboundedReceiverStream::new(outer_rx);
+        let mut thinking = false;
 
-        while let Some(message) = outer_receiver.next().await {
-            println!("{:?}", message);
+        while let Some(event) = outer_receiver.next().await {
+            match event {
+                ComposerEvent::ThoughtDelta(text) => {
+                    if !thinking {
+                        println!("[thinking]");
+                        thinking = true;
+                    }
+                    // Dimmed, so the reasoning stands apart from the answer.
+                    print!("\x1b[2m{}\x1b[0m", text);
+                    io::stdout().flush()?;
+                }
+                ComposerEvent::TextDelta(text) => {
+                    if thinking {
+                        println!("\n[answer]");
+                        thinking = false;
+                    }
+                    print!("{}", text);
+                    io::stdout().flush()?;
+                }
+                ComposerEvent::CodeExecutionStarted(executable_code) => {
+                    println!(
+                        "\n```{}\n{}\n```",
+                        executable_code.language.to_lowercase(),
+                        executable_code.code.trim_end()
+                    );
+                }
+                ComposerEvent::CodeExecutionFinished(code_execution_result) => {
+                    println!(
+                        "[{}]\n{}",
+                        code_execution_result.outcome,
+                        code_execution_result.output.trim_end()
+                    );
+                }
+                ComposerEvent::Grounded(grounding_metadata) => {
+                    let sources = grounding_metadata.grounding_chunks
+                        .iter()
+                        .filter_map(|grounding_chunk| grounding_chunk.web.as_ref())
+                        .collect::<Vec<_>>();
+                    if !sources.is_empty() {
+                        println!("\n\nSources:");
+                    }
+                    for (index, web) in sources.iter().enumerate() {
+                        println!("[{}] {} {}", index + 1, web.title, web.uri);
+                    }
+                }
+                ComposerEvent::Done => println!(),
+                ComposerEvent::Cancelled => println!("\n[cancelled]"),
+                event => println!("\n{:?}", event),
+            }
         }
 
+        running_turn.lock().unwrap().take();
+
         let h = handle.await;
         match h {
             Ok(_) => {
@@ -1,9 +1,50 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use async_trait::async_trait;
 use reqwest::header;
+use schemars::JsonSchema;
+use serde::Deserialize;
 
-use crate::data::CommandData;
+use crate::{
+    data::CommandData,
+    proto::message::{ FunctionCallPb, FunctionDeclarationPb },
+    tools::{ schema::function_declaration, Tool, ToolResult },
+};
+
+pub const BRAVE_SEARCH: &str = "web_search";
+
+#[derive(Deserialize, JsonSchema, Debug)]
+struct BraveSearchArgs {
+    /// The search query.
+    query: String,
+}
+
+pub struct BraveSearchTool;
+
+#[async_trait]
+impl Tool for BraveSearchTool {
+    fn name(&self) -> &'static str {
+        BRAVE_SEARCH
+    }
+
+    fn declaration(&self) -> FunctionDeclarationPb {
+        function_declaration::<BraveSearchArgs>(
+            BRAVE_SEARCH,
+            "Search the web for up to date information."
+        )
+    }
+
+    async fn execute(
+        &self,
+        command_data: Arc<CommandData>,
+        function_call: &FunctionCallPb
+    ) -> Result<ToolResult> {
+        let args: BraveSearchArgs = function_call.parse_args()?;
+
+        brave_search(command_data, args.query).await.map(ToolResult::Text)
+    }
+}
 
 pub async fn brave_search(command_data: Arc<CommandData>, query: String) -> Result<String> {
     let api_key = &command_data.brave_token;
@@ -1,103 +1,354 @@
 use std::sync::Arc;
 
 use crate::{
-    brave::brave_search,
-    gemini::api::BRAVE_SEARCH,
+    data::CommandData,
+    gemini::BLOCKED_FINISH_REASONS,
+    history::{ self, HistorySource },
+    llm,
+    memory,
     proto::message::{
-        CandidatePb,
+        CodeExecutionResultPb,
         ContentPb,
+        ExecutableCodePb,
         FunctionCallPb,
         FunctionResponsePb,
         GeminiRequestPb,
-        GeminiResponsePb,
+        GroundingMetadataPb,
         PartPb,
+        SafetyRatingPb,
+        UsageMetadataPb,
     },
+    tools::ToolResult,
 };
 use anyhow::{ bail, Result };
+use serde_json::json;
 use tokio::sync::mpsc::{ self, UnboundedSender };
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 
-use crate::{ data::CommandData, flux::generate_image, gemini::{ self, api::GENERATE_IMAGE } };
+// Upper bound on model -> function -> model round trips for a single prompt.
+const MAX_FUNCTION_ROUNDS: usize = 5;
 
+/// What happened during a turn, in the order it happened.
+#[derive(Debug, Clone)]
+pub enum ComposerEvent {
+    TextDelta(String),
+    /// A piece of the model's reasoning, only streamed when the request's thinking config
+    /// includes thoughts.
+    ThoughtDelta(String),
+    ToolCallStarted(FunctionCallPb),
+    ToolCallFinished {
+        name: String,
+        result: ToolResult,
+    },
+    /// The tool returned an error, the model gets the error as the call's response.
+    ToolCallFailed {
+        name: String,
+        error: String,
+    },
+    ImageGenerated {
+        url: String,
+    },
+    /// Code the model runs with Gemini's code execution, see `gemini::api::enable_code_execution`.
+    CodeExecutionStarted(ExecutableCodePb),
+    CodeExecutionFinished(CodeExecutionResultPb),
+    /// Google Search results the answer is grounded in, see `gemini::api::enable_google_search`.
+    Grounded(GroundingMetadataPb),
+    /// Token counts for one model call, not every backend reports usage.
+    UsageReported(UsageMetadataPb),
+    /// The prompt or the response was rejected by a safety filter. Nothing more is generated,
+    /// `Done` follows as for any finished turn.
+    Blocked {
+        reason: String,
+        safety_ratings: Vec<SafetyRatingPb>,
+    },
+    Error(String),
+    /// The turn is over, always the last event of a turn that did not fail.
+    Done,
+    /// The turn was stopped through its cancellation token, sent instead of `Done`.
+    Cancelled,
+}
+
+/// Runs a turn until the model answers, `cancellation_token` stops it early.
+/// A forced function call (see `gemini::api::force_function_call`) only applies to the first round.
+/// `history` is usually a `history::SessionHistory`, any other source works the same way.
 pub async fn invoker(
     command_data: Arc<CommandData>,
-    session_id: Arc<String>,
+    history: Arc<dyn HistorySource>,
+    gemini_request_pb: GeminiRequestPb,
+    outer_tx: UnboundedSender<ComposerEvent>,
+    cancellation_token: CancellationToken
+) -> Result<()> {
+    let result = run_turn(
+        command_data.clone(),
+        history.clone(),
+        gemini_request_pb,
+        &outer_tx,
+        &cancellation_token
+    ).await;
+
+    match &result {
+        Ok(_) if cancellation_token.is_cancelled() => {
+            let _ = outer_tx.send(ComposerEvent::Cancelled);
+            after_turn(command_data, history.as_ref());
+        }
+        Ok(_) => {
+            let _ = outer_tx.send(ComposerEvent::Done);
+            after_turn(command_data, history.as_ref());
+        }
+        Err(e) => {
+            let _ = outer_tx.send(ComposerEvent::Error(e.to_string()));
+        }
+    }
+
+    result
+}
+
+// Background upkeep once the turn's messages are saved.
+fn after_turn(command_data: Arc<CommandData>, history: &dyn HistorySource) {
+    let session_id = match history.session_id() {
+        Some(session_id) => Arc::new(session_id.to_string()),
+        None => {
+            return;
+        }
+    };
+    memory::remember_turns_in_background(command_data.clone(), session_id.clone());
+    history::summarize_in_background(command_data, session_id);
+}
+
+async fn run_turn(
+    command_data: Arc<CommandData>,
+    history: Arc<dyn HistorySource>,
     gemini_request_pb: GeminiRequestPb,
-    outer_tx: UnboundedSender<GeminiResponsePb>
+    outer_tx: &UnboundedSender<ComposerEvent>,
+    cancellation_token: &CancellationToken
 ) -> Result<()> {
-    let (inner_tx, inner_rx) = mpsc::unbounded_channel(); // Create a bounded channel
+    let mut gemini_request_pb = gemini_request_pb;
+    // Gemini can't combine Google Search grounding with function calls, search replaces them.
+    let google_search = gemini_request_pb.tools
+        .iter()
+        .any(|tool_pb| tool_pb.google_search.is_some());
+    if !google_search {
+        gemini_request_pb.tools.extend(command_data.tool_registry.tools_pb());
+    }
+
+    for _ in 0..MAX_FUNCTION_ROUNDS {
+        let function_responses = invoke_round(
+            command_data.clone(),
+            history.clone(),
+            gemini_request_pb.clone(),
+            outer_tx,
+            cancellation_token
+        ).await?;
+
+        if function_responses.is_empty() {
+            return Ok(());
+        }
+
+        // The model's function call was appended by llm::invoke while streaming,
+        // answer it so the model can read the results on the next round.
+        let function_content = ContentPb {
+            role: "function".into(),
+            parts: function_responses
+                .into_iter()
+                .map(|function_response| PartPb {
+                    function_response: Some(function_response),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        history.append(&command_data, &function_content).await?;
+
+        if cancellation_token.is_cancelled() {
+            return Ok(());
+        }
+
+        gemini_request_pb.contents = vec![];
+
+        // A forced call is answered, forcing it again would never let the model reply.
+        let forced = gemini_request_pb.tool_config
+            .as_ref()
+            .and_then(|tool_config| tool_config.function_calling_config.as_ref())
+            .is_some_and(|function_calling_config| function_calling_config.mode == "ANY");
+        if forced {
+            gemini_request_pb.tool_config = None;
+        }
+    }
+
+    bail!("Model did not produce an answer after {} function call rounds.", MAX_FUNCTION_ROUNDS)
+}
+
+// Streams a single model turn to outer_tx, returning the results of any function calls it made.
+async fn invoke_round(
+    command_data: Arc<CommandData>,
+    history: Arc<dyn HistorySource>,
+    gemini_request_pb: GeminiRequestPb,
+    outer_tx: &UnboundedSender<ComposerEvent>,
+    cancellation_token: &CancellationToken
+) -> Result<Vec<FunctionResponsePb>> {
+    let (inner_tx, inner_rx) = mpsc::unbounded_channel();
 
     let mut inner_receiver = UnboundedReceiverStream::new(inner_rx);
 
     let command_data_clone = command_data.clone();
+    let invoke_cancellation_token = cancellation_token.clone();
     let handle = tokio::spawn(async move {
-        gemini::invoke(command_data_clone, &session_id, &gemini_request_pb, inner_tx).await
+        llm::invoke(
+            command_data_clone,
+            history.as_ref(),
+            &gemini_request_pb,
+            inner_tx,
+            &invoke_cancellation_token
+        ).await
     });
 
+    let mut function_responses = vec![];
+    let mut usage_metadata = None;
+
     while let Some(message) = inner_receiver.next().await {
-        outer_tx.send(message.clone());
+        if message.usage_metadata.is_some() {
+            usage_metadata = message.usage_metadata;
+        }
 
-        let candidate = &message.candidates[0];
-        let parts = match &candidate.content {
-            Some(content) => &content.parts,
+        if let Some(prompt_feedback) = &message.prompt_feedback {
+            if let Some(block_reason) = &prompt_feedback.block_reason {
+                outer_tx.send(ComposerEvent::Blocked {
+                    reason: block_reason.clone(),
+                    safety_ratings: prompt_feedback.safety_ratings.clone(),
+                })?;
+                continue;
+            }
+        }
+
+        let candidate = match message.candidates.first() {
+            Some(candidate) => candidate,
             None => {
                 continue;
             }
         };
+        let parts = candidate.content
+            .as_ref()
+            .map(|content| content.parts.as_slice())
+            .unwrap_or_default();
 
         for part in parts {
             if let Some(text) = &part.text {
-                // do something
+                if text.is_empty() {
+                    continue;
+                }
+                if part.thought {
+                    outer_tx.send(ComposerEvent::ThoughtDelta(text.clone()))?;
+                } else {
+                    outer_tx.send(ComposerEvent::TextDelta(text.clone()))?;
+                }
+            } else if let Some(executable_code) = &part.executable_code {
+                outer_tx.send(ComposerEvent::CodeExecutionStarted(executable_code.clone()))?;
+            } else if let Some(code_execution_result) = &part.code_execution_result {
+                outer_tx.send(ComposerEvent::CodeExecutionFinished(code_execution_result.clone()))?;
             } else if let Some(function_call) = &part.function_call {
-                let result = handle_function_call(command_data.clone(), function_call).await?;
-
-                let gemini_response = GeminiResponsePb {
-                    candidates: vec![CandidatePb {
-                        content: Some(ContentPb {
-                            role: "model".into(),
-                            parts: vec![PartPb {
-                                text: None,
-                                function_call: None,
-                                function_response: Some(result),
-                            }],
-                        }),
-                        finish_reason: None,
-                    }],
+                // Every call needs a response, or the saved history is invalid for the next turn.
+                if cancellation_token.is_cancelled() {
+                    function_responses.push(cancelled_response(function_call));
+                    continue;
+                }
+
+                outer_tx.send(ComposerEvent::ToolCallStarted(function_call.clone()))?;
+
+                let result = tokio::select! {
+                    result = handle_function_call(command_data.clone(), function_call) => result,
+                    _ = cancellation_token.cancelled() => {
+                        function_responses.push(cancelled_response(function_call));
+                        continue;
+                    }
+                };
+
+                // A failed tool doesn't end the turn, the model reads the error and answers.
+                let result = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        let error = format!("{:#}", e);
+                        function_responses.push(error_response(function_call, &error));
+                        outer_tx.send(ComposerEvent::ToolCallFailed {
+                            name: function_call.name.clone(),
+                            error,
+                        })?;
+                        continue;
+                    }
                 };
 
-                outer_tx.send(gemini_response);
+                if let ToolResult::Image { url } = &result {
+                    outer_tx.send(ComposerEvent::ImageGenerated { url: url.clone() })?;
+                }
+
+                function_responses.push(
+                    FunctionResponsePb::new(
+                        function_call.name.clone(),
+                        function_call.id.clone(),
+                        &result.to_response()
+                    )
+                );
+
+                outer_tx.send(ComposerEvent::ToolCallFinished {
+                    name: function_call.name.clone(),
+                    result,
+                })?;
             }
         }
+
+        if let Some(grounding_metadata) = &candidate.grounding_metadata {
+            outer_tx.send(ComposerEvent::Grounded(grounding_metadata.clone()))?;
+        }
+
+        let blocked_reason = candidate.finish_reason
+            .as_deref()
+            .filter(|finish_reason| BLOCKED_FINISH_REASONS.contains(finish_reason));
+        if let Some(reason) = blocked_reason {
+            outer_tx.send(ComposerEvent::Blocked {
+                reason: reason.into(),
+                safety_ratings: candidate.safety_ratings.clone(),
+            })?;
+        }
+    }
+
+    handle.await??;
+
+    if let Some(usage_metadata) = usage_metadata {
+        outer_tx.send(ComposerEvent::UsageReported(usage_metadata))?;
     }
 
-    handle.await?
+    Ok(function_responses)
+}
+
+fn cancelled_response(function_call: &FunctionCallPb) -> FunctionResponsePb {
+    error_response(function_call, "Cancelled by the user.")
+}
+
+fn error_response(function_call: &FunctionCallPb, error: &str) -> FunctionResponsePb {
+    FunctionResponsePb::new(
+        function_call.name.clone(),
+        function_call.id.clone(),
+        &json!({ "error": error })
+    )
 }
 
+/// Runs the tool the model called. Errors are meant for the model, an unknown name or
+/// arguments that don't fit the tool are mistakes it can correct on the next round.
 pub async fn handle_function_call(
     command_data: Arc<CommandData>,
     function_call: &FunctionCallPb
-) -> Result<FunctionResponsePb> {
-    let result = match function_call.name.as_str() {
-        GENERATE_IMAGE => {
-            let prompt = function_call.args.get("prompt");
-            match prompt {
-                Some(prompt) => generate_image(command_data, prompt.into()).await,
-                None => { bail!("Prompt was not supplied to generate_image call.") }
-            }
-        }
-        BRAVE_SEARCH => {
-            let query = function_call.args.get("query");
-            match query {
-                Some(query) => brave_search(command_data, query.into()).await,
-                None => { bail!("Query was not supplied to web_search call.") }
-            }
+) -> Result<ToolResult> {
+    let tool = match command_data.tool_registry.get(&function_call.name) {
+        Some(tool) => tool,
+        None => {
+            bail!(
+                "Unknown function {}, available functions are: {}.",
+                function_call.name,
+                command_data.tool_registry.names().join(", ")
+            )
         }
-        _ => { bail!("Function call not supported.") }
     };
 
-    Ok(FunctionResponsePb {
-        name: function_call.name.clone(),
-        response: result?,
-    })
+    tool.execute(command_data.clone(), function_call).await
 }
@@ -0,0 +1,286 @@
+use std::{
+    env,
+    hash::{DefaultHasher, Hasher},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use prost::Message;
+
+use crate::{
+    data::{self, CommandData},
+    llm,
+    proto::message::{history_policy_pb::Policy, ContentPb, ContextCachePb, GeminiRequestPb},
+};
+
+/// Tokens the stable start of a session's requests needs before it's cached, Gemini won't
+/// cache less for most models. `CONTEXT_CACHE_MIN_TOKENS` overrides it, 0 turns caching off.
+const DEFAULT_MIN_TOKENS: i32 = 4096;
+/// How long a cache lives without being used, `CONTEXT_CACHE_TTL_SECONDS` overrides it.
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+// Caches this close to expiring are replaced, they could be gone before the request arrives.
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+/// Starts the request from the session's context cache. The cache holds the system
+/// instruction, tools and history before the current turn, it's created once those pass the
+/// token threshold and replaced once the history added since passes it too. Caches in use
+/// are extended, caches that no longer match the request are deleted. Requests without a
+/// cache worth having are left as they are.
+pub async fn apply(
+    command_data: &CommandData,
+    session_id: &str,
+    gemini_request_pb: &mut GeminiRequestPb,
+) -> Result<()> {
+    let min_tokens = min_tokens();
+    // A tool config only lasts a round or a command, and a cache has to carry it.
+    if min_tokens <= 0
+        || !command_data.llm_provider.supports_caching()
+        || gemini_request_pb.cached_content.is_some()
+        || gemini_request_pb.tool_config.is_some()
+    {
+        return Ok(());
+    }
+
+    let prefix_len = stable_prefix_len(command_data, session_id, gemini_request_pb).await?;
+    let now = unix_now();
+
+    let context_cache = match data::get_context_cache(command_data, session_id).await? {
+        Some(context_cache) if covers(&context_cache, gemini_request_pb, prefix_len, now) => {
+            Some(context_cache)
+        }
+        Some(stale) => {
+            discard(command_data, session_id, &stale).await?;
+            None
+        }
+        None => None,
+    };
+
+    let context_cache = match context_cache {
+        Some(context_cache) => {
+            let uncached =
+                &gemini_request_pb.contents[context_cache.content_count as usize..prefix_len];
+            if reaches(command_data, gemini_request_pb, uncached, min_tokens).await? {
+                discard(command_data, session_id, &context_cache).await?;
+                create(command_data, session_id, gemini_request_pb, prefix_len).await?
+            } else {
+                extend_if_expiring(command_data, session_id, context_cache, now).await?
+            }
+        }
+        None => {
+            let mut prefix = system_instruction_contents(gemini_request_pb);
+            prefix.extend_from_slice(&gemini_request_pb.contents[..prefix_len]);
+            if !reaches(command_data, gemini_request_pb, &prefix, min_tokens).await? {
+                return Ok(());
+            }
+            create(command_data, session_id, gemini_request_pb, prefix_len).await?
+        }
+    };
+
+    start_from_cache(gemini_request_pb, &context_cache);
+
+    Ok(())
+}
+
+fn min_tokens() -> i32 {
+    env::var("CONTEXT_CACHE_MIN_TOKENS")
+        .ok()
+        .and_then(|min_tokens| min_tokens.parse().ok())
+        .unwrap_or(DEFAULT_MIN_TOKENS)
+}
+
+fn ttl() -> Duration {
+    env::var("CONTEXT_CACHE_TTL_SECONDS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .map_or(DEFAULT_TTL, Duration::from_secs)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+// History before the current turn's prompt doesn't change between requests, unless the
+// history policy drops the oldest messages as new ones come in.
+async fn stable_prefix_len(
+    command_data: &CommandData,
+    session_id: &str,
+    gemini_request_pb: &GeminiRequestPb,
+) -> Result<usize> {
+    let policy = data::get_history_policy(command_data, session_id)
+        .await?
+        .and_then(|history_policy| history_policy.policy);
+    if matches!(policy, Some(Policy::LastN(_) | Policy::TokenBudget(_))) {
+        return Ok(0);
+    }
+
+    Ok(gemini_request_pb
+        .contents
+        .iter()
+        .rposition(|content| content.role == "user")
+        .unwrap_or(0))
+}
+
+fn covers(
+    context_cache: &ContextCachePb,
+    gemini_request_pb: &GeminiRequestPb,
+    prefix_len: usize,
+    now: i64,
+) -> bool {
+    let content_count = context_cache.content_count as usize;
+
+    context_cache.expires_at > now + EXPIRY_MARGIN_SECONDS
+        && content_count <= prefix_len
+        && context_cache.prefix_hash == prefix_hash(gemini_request_pb, content_count)
+}
+
+// Hashes what a cache of the request's first `content_count` contents holds. The hasher
+// may change between Rust versions, that only costs a new cache.
+fn prefix_hash(gemini_request_pb: &GeminiRequestPb, content_count: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(
+        gemini_request_pb
+            .model
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    if let Some(system_instruction) = &gemini_request_pb.system_instruction {
+        hasher.write(&system_instruction.encode_to_vec());
+    }
+    for tool in &gemini_request_pb.tools {
+        hasher.write(&tool.encode_to_vec());
+    }
+    for content in &gemini_request_pb.contents[..content_count] {
+        hasher.write(&content.encode_to_vec());
+    }
+    hasher.finish()
+}
+
+fn system_instruction_contents(gemini_request_pb: &GeminiRequestPb) -> Vec<ContentPb> {
+    gemini_request_pb
+        .system_instruction
+        .iter()
+        .map(|system_instruction| ContentPb {
+            role: "user".into(),
+            parts: system_instruction.parts.clone(),
+            cancelled: false,
+        })
+        .collect()
+}
+
+// Counting tokens is a request of its own, text that's clearly too short is ruled out by
+// estimate. Attachments aren't estimated, contents with them are always counted.
+async fn reaches(
+    command_data: &CommandData,
+    gemini_request_pb: &GeminiRequestPb,
+    contents: &[ContentPb],
+    min_tokens: i32,
+) -> Result<bool> {
+    if contents.is_empty() {
+        return Ok(false);
+    }
+
+    let has_attachments = contents
+        .iter()
+        .flat_map(|content| content.parts.iter())
+        .any(|part| part.inline_data.is_some() || part.file_data.is_some());
+    if !has_attachments && llm::estimate_tokens(contents) < min_tokens / 2 {
+        return Ok(false);
+    }
+
+    Ok(command_data
+        .llm_provider
+        .count_tokens(gemini_request_pb.model.as_deref(), contents)
+        .await?
+        >= min_tokens)
+}
+
+async fn create(
+    command_data: &CommandData,
+    session_id: &str,
+    gemini_request_pb: &GeminiRequestPb,
+    prefix_len: usize,
+) -> Result<ContextCachePb> {
+    let mut prefix_request_pb = gemini_request_pb.clone();
+    prefix_request_pb.contents.truncate(prefix_len);
+
+    let ttl = ttl();
+    let cached_content_pb = command_data
+        .llm_provider
+        .create_cache(&prefix_request_pb, ttl)
+        .await?;
+
+    let context_cache = ContextCachePb {
+        name: cached_content_pb.name,
+        model: cached_content_pb.model,
+        prefix_hash: prefix_hash(gemini_request_pb, prefix_len),
+        content_count: prefix_len as u32,
+        expires_at: unix_now() + ttl.as_secs() as i64,
+    };
+    data::set_context_cache(command_data, session_id, &context_cache).await?;
+
+    Ok(context_cache)
+}
+
+// Extends the cache once half its time is up, rather than on every request.
+async fn extend_if_expiring(
+    command_data: &CommandData,
+    session_id: &str,
+    mut context_cache: ContextCachePb,
+    now: i64,
+) -> Result<ContextCachePb> {
+    let ttl = ttl();
+    if context_cache.expires_at - now > ttl.as_secs() as i64 / 2 {
+        return Ok(context_cache);
+    }
+
+    command_data
+        .llm_provider
+        .update_cache_ttl(&context_cache.name, ttl)
+        .await?;
+    context_cache.expires_at = now + ttl.as_secs() as i64;
+    data::set_context_cache(command_data, session_id, &context_cache).await?;
+
+    Ok(context_cache)
+}
+
+async fn discard(
+    command_data: &CommandData,
+    session_id: &str,
+    context_cache: &ContextCachePb,
+) -> Result<()> {
+    // Expired caches are gone already, and a cache left behind expires on its own.
+    if context_cache.expires_at > unix_now() {
+        if let Err(e) = command_data
+            .llm_provider
+            .delete_cache(&context_cache.name)
+            .await
+        {
+            println!(
+                "Failed to delete context cache {}: {}",
+                context_cache.name, e
+            );
+        }
+    }
+
+    data::delete_context_cache(command_data, session_id).await
+}
+
+// The cache stands in for everything it holds, requests can't repeat any of it.
+fn start_from_cache(gemini_request_pb: &mut GeminiRequestPb, context_cache: &ContextCachePb) {
+    gemini_request_pb.cached_content = Some(context_cache.name.clone());
+    gemini_request_pb.model = Some(
+        context_cache
+            .model
+            .trim_start_matches("models/")
+            .to_string(),
+    );
+    gemini_request_pb
+        .contents
+        .drain(..context_cache.content_count as usize);
+    gemini_request_pb.system_instruction = None;
+    gemini_request_pb.tools.clear();
+}
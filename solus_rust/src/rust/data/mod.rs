@@ -1,4 +1,8 @@
-use crate::proto::message::ContentPb;
+use crate::{
+    llm::LlmProvider,
+    proto::message::{ ContentPb, ContextCachePb, HistoryPolicyPb, UsageMetadataPb },
+    tools::ToolRegistry,
+};
 use anyhow::Result;
 use reqwest::Client;
 use rusqlite::{ params, Connection, OptionalExtension };
@@ -6,26 +10,35 @@ use tokio::sync::Mutex;
 use uuid::Uuid;
 use prost::Message;
 
+/// A piece of an ingested document, `offset` is where it starts in the document's text.
+pub struct DocumentChunk {
+    pub source: String,
+    pub offset: i64,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
 pub struct CommandData {
     pub reqwest_client: Client,
     pub connection: Mutex<Connection>,
     pub replicate_token: String,
-    pub gemini_token: String,
     pub brave_token: String,
+    pub tool_registry: ToolRegistry,
+    pub llm_provider: Box<dyn LlmProvider>,
 }
 
 pub async fn setup(command_data: &CommandData) -> Result<()> {
     let conn = &command_data.connection.lock().await;
 
     conn.execute(
-        "CREATE TABLE ChatSessions (
+        "CREATE TABLE IF NOT EXISTS ChatSessions (
             id TEXT PRIMARY KEY
         )",
         () // empty list of parameters.
     )?;
 
     conn.execute(
-        "CREATE TABLE Messages (
+        "CREATE TABLE IF NOT EXISTS Messages (
             id TEXT PRIMARY KEY,
             session_id TEXT NOT NULL,
             content BLOB NOT NULL,
@@ -34,6 +47,76 @@ pub async fn setup(command_data: &CommandData) -> Result<()> {
         ()
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Usage (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            prompt_token_count INTEGER NOT NULL,
+            candidates_token_count INTEGER NOT NULL,
+            total_token_count INTEGER NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (session_id) REFERENCES ChatSessions(id)
+        )",
+        ()
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS HistoryPolicies (
+            session_id TEXT PRIMARY KEY,
+            policy BLOB NOT NULL,
+            FOREIGN KEY (session_id) REFERENCES ChatSessions(id)
+        )",
+        ()
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Summaries (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            last_message_rowid INTEGER NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (session_id) REFERENCES ChatSessions(id)
+        )",
+        ()
+    )?;
+
+    // message_rowid is the last message of a remembered turn, NULL for saved facts.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS Memories (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            text TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            message_rowid INTEGER,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (session_id) REFERENCES ChatSessions(id)
+        )",
+        ()
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ContextCaches (
+            session_id TEXT PRIMARY KEY,
+            cache BLOB NOT NULL,
+            FOREIGN KEY (session_id) REFERENCES ChatSessions(id)
+        )",
+        ()
+    )?;
+
+    // Chunks of ingested documents, shared by every session.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS DocumentChunks (
+            id TEXT PRIMARY KEY,
+            source TEXT NOT NULL,
+            offset INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+        ()
+    )?;
+
     Ok(())
 }
 
@@ -64,30 +147,38 @@ pub async fn get_or_create_session(command_data: &CommandData, id: String) -> Re
     }
 }
 
-pub async fn get_content(command_data: &CommandData, session_id: &str) -> Result<Vec<ContentPb>> {
+/// The session's messages in order, each with its rowid.
+pub async fn get_messages(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Vec<(i64, ContentPb)>> {
     let conn = &command_data.connection.lock().await;
 
-    let session_id = session_id.to_owned();
-    let mut statement = conn.prepare("SELECT content FROM Messages WHERE session_id = ?1")?;
+    let mut statement = conn.prepare(
+        "SELECT rowid, content FROM Messages WHERE session_id = ?1 ORDER BY rowid"
+    )?;
 
     let entries = statement
         .query_map(params![session_id], |row| {
-            let bytes = row.get::<_, Vec<u8>>(0)?;
-            Ok(bytes)
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
         })?
         .filter_map(|result| result.ok())
-        .map(|bytes| ContentPb::decode(bytes.as_slice()))
-        .filter_map(|result| result.ok())
+        .filter_map(|(rowid, bytes)| {
+            ContentPb::decode(bytes.as_slice())
+                .ok()
+                .map(|content| (rowid, content))
+        })
         .collect();
 
     Ok(entries)
 }
 
+/// Saves `content` to the session, returning the id of the new message.
 pub async fn add_content(
     command_data: &CommandData,
     session_id: &str,
     content: &ContentPb
-) -> Result<()> {
+) -> Result<String> {
     let connection = &command_data.connection.lock().await;
     let message_id = Uuid::new_v4().to_string();
 
@@ -96,5 +187,319 @@ pub async fn add_content(
         params![message_id, session_id, content.encode_to_vec()]
     )?;
 
+    Ok(message_id)
+}
+
+pub async fn update_content(
+    command_data: &CommandData,
+    message_id: &str,
+    content: &ContentPb
+) -> Result<()> {
+    let connection = &command_data.connection.lock().await;
+
+    connection.execute(
+        "UPDATE Messages SET content = ?1 WHERE id = ?2",
+        params![content.encode_to_vec(), message_id]
+    )?;
+
+    Ok(())
+}
+
+pub async fn add_usage(
+    command_data: &CommandData,
+    session_id: &str,
+    usage_metadata: &UsageMetadataPb
+) -> Result<()> {
+    let connection = &command_data.connection.lock().await;
+    let usage_id = Uuid::new_v4().to_string();
+
+    connection.execute(
+        "INSERT INTO Usage (id, session_id, prompt_token_count, candidates_token_count, total_token_count)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            usage_id,
+            session_id,
+            usage_metadata.prompt_token_count,
+            usage_metadata.candidates_token_count,
+            usage_metadata.total_token_count
+        ]
+    )?;
+
+    Ok(())
+}
+
+pub async fn get_session_usage(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<UsageMetadataPb> {
+    let conn = &command_data.connection.lock().await;
+
+    let usage = conn.query_row(
+        "SELECT
+            COALESCE(SUM(prompt_token_count), 0),
+            COALESCE(SUM(candidates_token_count), 0),
+            COALESCE(SUM(total_token_count), 0)
+        FROM Usage WHERE session_id = ?1",
+        params![session_id],
+        usage_from_row
+    )?;
+
+    Ok(usage)
+}
+
+/// Totals across all sessions for each UTC day (`YYYY-MM-DD`), most recent first.
+pub async fn get_daily_usage(command_data: &CommandData) -> Result<Vec<(String, UsageMetadataPb)>> {
+    let conn = &command_data.connection.lock().await;
+
+    let mut statement = conn.prepare(
+        "SELECT
+            SUM(prompt_token_count),
+            SUM(candidates_token_count),
+            SUM(total_token_count),
+            date(created_at) AS day
+        FROM Usage GROUP BY day ORDER BY day DESC"
+    )?;
+
+    let entries = statement
+        .query_map((), |row| Ok((row.get::<_, String>(3)?, usage_from_row(row)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(entries)
+}
+
+fn usage_from_row(row: &rusqlite::Row) -> rusqlite::Result<UsageMetadataPb> {
+    Ok(UsageMetadataPb {
+        prompt_token_count: row.get::<_, i64>(0)?,
+        candidates_token_count: row.get::<_, i64>(1)?,
+        total_token_count: row.get::<_, i64>(2)?,
+    })
+}
+
+pub async fn get_history_policy(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Option<HistoryPolicyPb>> {
+    let conn = &command_data.connection.lock().await;
+
+    let policy = conn
+        .query_row(
+            "SELECT policy FROM HistoryPolicies WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get::<_, Vec<u8>>(0)
+        )
+        .optional()?;
+
+    Ok(policy.and_then(|bytes| HistoryPolicyPb::decode(bytes.as_slice()).ok()))
+}
+
+pub async fn set_history_policy(
+    command_data: &CommandData,
+    session_id: &str,
+    history_policy: &HistoryPolicyPb
+) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+
+    conn.execute(
+        "INSERT INTO HistoryPolicies (session_id, policy) VALUES (?1, ?2)
+        ON CONFLICT(session_id) DO UPDATE SET policy = excluded.policy",
+        params![session_id, history_policy.encode_to_vec()]
+    )?;
+
+    Ok(())
+}
+
+pub async fn get_context_cache(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Option<ContextCachePb>> {
+    let conn = &command_data.connection.lock().await;
+
+    let cache = conn
+        .query_row(
+            "SELECT cache FROM ContextCaches WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get::<_, Vec<u8>>(0)
+        )
+        .optional()?;
+
+    Ok(cache.and_then(|bytes| ContextCachePb::decode(bytes.as_slice()).ok()))
+}
+
+pub async fn set_context_cache(
+    command_data: &CommandData,
+    session_id: &str,
+    context_cache: &ContextCachePb
+) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+
+    conn.execute(
+        "INSERT INTO ContextCaches (session_id, cache) VALUES (?1, ?2)
+        ON CONFLICT(session_id) DO UPDATE SET cache = excluded.cache",
+        params![session_id, context_cache.encode_to_vec()]
+    )?;
+
+    Ok(())
+}
+
+pub async fn delete_context_cache(command_data: &CommandData, session_id: &str) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+
+    conn.execute("DELETE FROM ContextCaches WHERE session_id = ?1", params![session_id])?;
+
+    Ok(())
+}
+
+/// The newest summary of the session and the rowid of the last message it covers.
+pub async fn get_latest_summary(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Option<(String, i64)>> {
+    let conn = &command_data.connection.lock().await;
+
+    let summary = conn
+        .query_row(
+            "SELECT summary, last_message_rowid FROM Summaries WHERE session_id = ?1
+            ORDER BY last_message_rowid DESC LIMIT 1",
+            params![session_id],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )
+        .optional()?;
+
+    Ok(summary)
+}
+
+pub async fn add_summary(
+    command_data: &CommandData,
+    session_id: &str,
+    summary: &str,
+    last_message_rowid: i64
+) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+    let summary_id = Uuid::new_v4().to_string();
+
+    conn.execute(
+        "INSERT INTO Summaries (id, session_id, summary, last_message_rowid) VALUES (?1, ?2, ?3, ?4)",
+        params![summary_id, session_id, summary, last_message_rowid]
+    )?;
+
+    Ok(())
+}
+
+pub async fn add_memory(
+    command_data: &CommandData,
+    session_id: &str,
+    text: &str,
+    embedding: &[f32],
+    message_rowid: Option<i64>
+) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+    let memory_id = Uuid::new_v4().to_string();
+
+    conn.execute(
+        "INSERT INTO Memories (id, session_id, text, embedding, message_rowid) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![memory_id, session_id, text, embedding_to_blob(embedding), message_rowid]
+    )?;
+
+    Ok(())
+}
+
+/// Every memory of the session with its embedding.
+pub async fn get_memories(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Vec<(String, Vec<f32>)>> {
+    let conn = &command_data.connection.lock().await;
+
+    let mut statement = conn.prepare("SELECT text, embedding FROM Memories WHERE session_id = ?1")?;
+
+    let entries = statement
+        .query_map(params![session_id], |row| {
+            let embedding: Vec<u8> = row.get(1)?;
+            Ok((row.get(0)?, embedding_from_blob(&embedding)))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(entries)
+}
+
+/// Rowid of the last message already remembered, turns after it are not remembered yet.
+pub async fn get_last_remembered_rowid(
+    command_data: &CommandData,
+    session_id: &str
+) -> Result<Option<i64>> {
+    let conn = &command_data.connection.lock().await;
+
+    let rowid = conn.query_row(
+        "SELECT MAX(message_rowid) FROM Memories WHERE session_id = ?1",
+        params![session_id],
+        |row| row.get(0)
+    )?;
+
+    Ok(rowid)
+}
+
+/// Replaces every chunk of `source`, so ingesting a document again updates it.
+pub async fn replace_document_chunks(
+    command_data: &CommandData,
+    source: &str,
+    chunks: &[DocumentChunk]
+) -> Result<()> {
+    let conn = &command_data.connection.lock().await;
+    let transaction = conn.unchecked_transaction()?;
+
+    transaction.execute("DELETE FROM DocumentChunks WHERE source = ?1", params![source])?;
+    for chunk in chunks {
+        let chunk_id = Uuid::new_v4().to_string();
+        transaction.execute(
+            "INSERT INTO DocumentChunks (id, source, offset, text, embedding) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                chunk_id,
+                chunk.source,
+                chunk.offset,
+                chunk.text,
+                embedding_to_blob(&chunk.embedding)
+            ]
+        )?;
+    }
+
+    transaction.commit()?;
+
     Ok(())
 }
+
+pub async fn get_document_chunks(command_data: &CommandData) -> Result<Vec<DocumentChunk>> {
+    let conn = &command_data.connection.lock().await;
+
+    let mut statement = conn.prepare(
+        "SELECT source, offset, text, embedding FROM DocumentChunks"
+    )?;
+
+    let entries = statement
+        .query_map((), |row| {
+            let embedding: Vec<u8> = row.get(3)?;
+            Ok(DocumentChunk {
+                source: row.get(0)?,
+                offset: row.get(1)?,
+                text: row.get(2)?,
+                embedding: embedding_from_blob(&embedding),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(entries)
+}
+
+// Embeddings are stored as little-endian f32s.
+fn embedding_to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn embedding_from_blob(blob: &[u8]) -> Vec<f32> {
+    blob
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
@@ -0,0 +1,340 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    data::{self, CommandData, DocumentChunk},
+    llm::EmbeddingTask,
+    memory::cosine_similarity,
+    proto::message::{FunctionCallPb, FunctionDeclarationPb},
+    tools::{schema::function_declaration, Tool, ToolResult},
+};
+
+pub const SEARCH_DOCUMENTS: &str = "search_documents";
+
+/// Bytes of text per chunk, small enough to embed and to quote a few of in an answer.
+const CHUNK_SIZE: usize = 1500;
+/// Bytes shared by neighbouring chunks, so a passage cut in two is still found whole.
+const CHUNK_OVERLAP: usize = 200;
+/// Chunks returned by a search.
+const TOP_K: usize = 5;
+
+/// A chunk found by `search`, with how similar it is to the query.
+#[derive(Debug, Clone)]
+pub struct DocumentMatch {
+    pub source: String,
+    pub offset: i64,
+    pub text: String,
+    pub similarity: f32,
+}
+
+/// Ingests a Markdown, text or PDF file, cited by its path. Returns the number of chunks.
+pub async fn ingest_file(command_data: &CommandData, path: &Path) -> Result<usize> {
+    let bytes = tokio::fs::read(path).await?;
+    ingest(command_data, &path.display().to_string(), bytes).await
+}
+
+/// Extracts, chunks and embeds a document, replacing an earlier version of `source`.
+/// `source`'s extension decides how the text is read. Returns the number of chunks.
+pub async fn ingest(command_data: &CommandData, source: &str, bytes: Vec<u8>) -> Result<usize> {
+    let text = extract_text(source, bytes).await?;
+    let chunks = chunk_text(&text);
+    if chunks.is_empty() {
+        bail!("{} has no text.", source);
+    }
+
+    let texts: Vec<String> = chunks.iter().map(|(_, text)| text.to_string()).collect();
+    let embeddings = command_data
+        .llm_provider
+        .embed(&texts, EmbeddingTask::Document)
+        .await?;
+
+    let document_chunks: Vec<DocumentChunk> = chunks
+        .into_iter()
+        .zip(embeddings)
+        .map(|((offset, text), embedding)| DocumentChunk {
+            source: source.into(),
+            offset: offset as i64,
+            text: text.into(),
+            embedding,
+        })
+        .collect();
+
+    data::replace_document_chunks(command_data, source, &document_chunks).await?;
+
+    Ok(document_chunks.len())
+}
+
+async fn extract_text(source: &str, bytes: Vec<u8>) -> Result<String> {
+    let extension = Path::new(source)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("md" | "markdown" | "txt") => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        // Parsing a large PDF takes a while, keep it off the async workers.
+        Some("pdf") => {
+            let text =
+                tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes))
+                    .await??;
+            Ok(text)
+        }
+        _ => bail!("{} is not a Markdown, text or PDF file.", source),
+    }
+}
+
+// Splits into overlapping chunks of up to CHUNK_SIZE bytes, preferring to end them
+// at a paragraph, then at a word. Returns each chunk with its byte offset.
+fn chunk_text(text: &str) -> Vec<(usize, &str)> {
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < text.len() {
+        let mut end = floor_char_boundary(text, start + CHUNK_SIZE);
+        if end < text.len() {
+            let window = &text[start..end];
+            // A split too early would make a short chunk, each candidate has to pass this.
+            let late_enough = |split: &usize| *split > CHUNK_SIZE / 2;
+            if let Some(split) = window
+                .rfind("\n\n")
+                .filter(late_enough)
+                .or_else(|| window.rfind(char::is_whitespace).filter(late_enough))
+            {
+                end = start + split;
+            }
+        }
+
+        let chunk = &text[start..end];
+        let trimmed = chunk.trim_start();
+        let offset = start + (chunk.len() - trimmed.len());
+        let trimmed = trimmed.trim_end();
+        if !trimmed.is_empty() {
+            chunks.push((offset, trimmed));
+        }
+
+        if end >= text.len() {
+            break;
+        }
+        // The overlap starts at a word, leading whitespace is trimmed above.
+        let overlap_start = floor_char_boundary(text, end.saturating_sub(CHUNK_OVERLAP));
+        let next = text[overlap_start..end]
+            .find(char::is_whitespace)
+            .map_or(overlap_start, |space| overlap_start + space);
+        start = if next > start { next } else { end };
+    }
+
+    chunks
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// The ingested chunks most similar to `query`, most similar first.
+pub async fn search(
+    command_data: &CommandData,
+    query: &str,
+    top_k: usize,
+) -> Result<Vec<DocumentMatch>> {
+    let document_chunks = data::get_document_chunks(command_data).await?;
+    if document_chunks.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let query_embedding = command_data
+        .llm_provider
+        .embed(&[query.to_string()], EmbeddingTask::Query)
+        .await?
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+
+    let mut matches: Vec<DocumentMatch> = document_chunks
+        .into_iter()
+        .map(|document_chunk| DocumentMatch {
+            similarity: cosine_similarity(&query_embedding, &document_chunk.embedding),
+            source: document_chunk.source,
+            offset: document_chunk.offset,
+            text: document_chunk.text,
+        })
+        .collect();
+    matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    matches.truncate(top_k);
+
+    Ok(matches)
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+struct SearchDocumentsArgs {
+    /// What to look for, phrased like the passage that would answer it.
+    query: String,
+}
+
+pub struct SearchDocumentsTool;
+
+#[async_trait]
+impl Tool for SearchDocumentsTool {
+    fn name(&self) -> &'static str {
+        SEARCH_DOCUMENTS
+    }
+
+    fn declaration(&self) -> FunctionDeclarationPb {
+        function_declaration::<SearchDocumentsArgs>(
+            SEARCH_DOCUMENTS,
+            "Search the team's own documents. Use it for questions about internal projects, \
+            processes or anything the documents may cover. Cite the excerpts you use as \
+            [source:offset].",
+        )
+    }
+
+    async fn execute(
+        &self,
+        command_data: Arc<CommandData>,
+        function_call: &FunctionCallPb,
+    ) -> Result<ToolResult> {
+        let args: SearchDocumentsArgs = function_call.parse_args()?;
+
+        let matches = search(&command_data, &args.query, TOP_K).await?;
+        if matches.is_empty() {
+            return Ok(ToolResult::Text("No matching documents.".into()));
+        }
+
+        let excerpts = matches
+            .iter()
+            .map(|document_match| {
+                format!(
+                    "[{}:{}]\n{}",
+                    document_match.source, document_match.offset, document_match.text
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        Ok(ToolResult::Text(format!(
+            "Excerpts, cite them as [source:offset]:\n\n{}",
+            excerpts
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(count: usize) -> String {
+        (0..count)
+            .map(|index| format!("word{}", index))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // Every chunk is the slice of `text` at its offset, and no longer than CHUNK_SIZE.
+    fn assert_chunks_point_into(text: &str, chunks: &[(usize, &str)]) {
+        for (offset, chunk) in chunks {
+            assert!(chunk.len() <= CHUNK_SIZE);
+            assert_eq!(&text[*offset..*offset + chunk.len()], *chunk);
+        }
+    }
+
+    #[test]
+    fn text_shorter_than_a_chunk_is_one_trimmed_chunk() {
+        let text = "\n  A short note.  \n";
+
+        let chunks = chunk_text(text);
+
+        assert_eq!(chunks, vec![(3, "A short note.")]);
+    }
+
+    #[test]
+    fn blank_text_has_no_chunks() {
+        assert!(chunk_text("").is_empty());
+        assert!(chunk_text(" \n\n\t ").is_empty());
+    }
+
+    #[test]
+    fn multibyte_text_is_split_on_char_boundaries() {
+        // Two and three byte characters, without whitespace to split at.
+        let text = "é日".repeat(CHUNK_SIZE);
+
+        let chunks = chunk_text(&text);
+
+        assert!(chunks.len() > 1);
+        assert_chunks_point_into(&text, &chunks);
+        let (last_offset, last_chunk) = chunks.last().unwrap();
+        assert_eq!(last_offset + last_chunk.len(), text.len());
+    }
+
+    #[test]
+    fn multibyte_text_with_spaces_ends_chunks_at_words() {
+        let text = "héllo wörld 日本語 ".repeat(CHUNK_SIZE / 10);
+
+        let chunks = chunk_text(&text);
+
+        assert!(chunks.len() > 1);
+        assert_chunks_point_into(&text, &chunks);
+        for (offset, chunk) in &chunks[1..] {
+            assert!(text[..*offset].ends_with(' '), "{:?}", chunk);
+        }
+    }
+
+    #[test]
+    fn neighbouring_chunks_overlap_by_at_most_chunk_overlap() {
+        let text = words(2000);
+
+        let chunks = chunk_text(&text);
+
+        assert!(chunks.len() > 1);
+        assert_chunks_point_into(&text, &chunks);
+        for pair in chunks.windows(2) {
+            let (offset, chunk) = pair[0];
+            let (next_offset, next_chunk) = pair[1];
+            let end = offset + chunk.len();
+            assert!(next_offset > offset);
+            assert!(
+                next_offset < end,
+                "chunks at {} and {} don't overlap",
+                offset,
+                next_offset
+            );
+            assert!(end - next_offset <= CHUNK_OVERLAP);
+            // The overlap is the same text in both chunks.
+            assert!(chunk.ends_with(&next_chunk[..end - next_offset]));
+        }
+        let (last_offset, last_chunk) = chunks.last().unwrap();
+        assert_eq!(last_offset + last_chunk.len(), text.len());
+    }
+
+    #[test]
+    fn chunks_fall_back_to_a_word_after_an_early_paragraph() {
+        let text = format!("A short heading\n\n{}", words(400));
+
+        let chunks = chunk_text(&text);
+
+        assert!(chunks.len() > 1);
+        let (offset, chunk) = chunks[0];
+        let end = offset + chunk.len();
+        assert!(end < CHUNK_SIZE);
+        assert!(
+            text[end..].starts_with(' '),
+            "{:?}",
+            &text[end - 10..end + 10]
+        );
+    }
+
+    #[test]
+    fn chunks_prefer_to_end_at_a_paragraph() {
+        let text = format!("{}\n\n{}", "a ".repeat(500).trim_end(), words(400));
+
+        let chunks = chunk_text(&text);
+
+        assert_eq!(chunks[0].1, "a ".repeat(500).trim_end());
+    }
+}
@@ -1,17 +1,57 @@
 use std::sync::Arc;
 
 use anyhow::{ bail, Result };
+use async_trait::async_trait;
 use reqwest::header;
+use schemars::JsonSchema;
 use serde::{ Deserialize, Serialize };
 use serde_json::json;
 
-use crate::data::CommandData;
+use crate::{
+    data::CommandData,
+    proto::message::{ FunctionCallPb, FunctionDeclarationPb },
+    tools::{ schema::function_declaration, Tool, ToolResult },
+};
+
+pub const GENERATE_IMAGE: &str = "generate_image";
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ReplicateResponse {
     output: Vec<String>,
 }
 
+#[derive(Deserialize, JsonSchema, Debug)]
+struct GenerateImageArgs {
+    /// A detailed description of the image to generate.
+    prompt: String,
+}
+
+pub struct GenerateImageTool;
+
+#[async_trait]
+impl Tool for GenerateImageTool {
+    fn name(&self) -> &'static str {
+        GENERATE_IMAGE
+    }
+
+    fn declaration(&self) -> FunctionDeclarationPb {
+        function_declaration::<GenerateImageArgs>(
+            GENERATE_IMAGE,
+            "Generate an image from a text prompt. Returns the URL of the generated image."
+        )
+    }
+
+    async fn execute(
+        &self,
+        command_data: Arc<CommandData>,
+        function_call: &FunctionCallPb
+    ) -> Result<ToolResult> {
+        let args: GenerateImageArgs = function_call.parse_args()?;
+
+        generate_image(command_data, args.prompt).await.map(|url| ToolResult::Image { url })
+    }
+}
+
 pub async fn generate_image(command_data: Arc<CommandData>, prompt: String) -> Result<String> {
     let reqwest_client = &command_data.reqwest_client;
     let replicate_token = &command_data.replicate_token;
@@ -1,10 +1,11 @@
 use std::{collections::HashMap, vec};
 
 use crate::proto::message::{
-    ContentPb, FunctionDeclarationPb, FunctionParameterPb, FunctionParametersPb, GeminiRequestPb,
-    PartPb, SystemInstructionPb, ToolPb,
+    BlobPb, CodeExecutionPb, ContentPb, FunctionCallingConfigPb, GeminiRequestPb, GoogleSearchPb,
+    PartPb, SystemInstructionPb, ToolConfigPb, ToolPb,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GeminiRequest {
@@ -12,6 +13,64 @@ pub struct GeminiRequest {
     pub tools: Vec<Tool>,
     #[serde(rename = "systemInstruction")]
     pub system_instruction: Option<SystemInstruction>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+    #[serde(rename = "safetySettings", skip_serializing_if = "Vec::is_empty")]
+    pub safety_settings: Vec<SafetySetting>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    #[serde(rename = "cachedContent", skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_function_names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<FunctionParameter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_config: Option<ThinkingConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ThinkingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<i32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub include_thoughts: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,12 +80,46 @@ pub struct SystemInstruction {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GeminiResponse {
+    // Missing when the prompt is blocked.
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
+    #[serde(rename = "usageMetadata")]
+    pub usage_metadata: Option<UsageMetadata>,
+    #[serde(rename = "promptFeedback")]
+    pub prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+    #[serde(default)]
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SafetyRating {
+    pub category: String,
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: i32,
+    #[serde(default)]
+    pub candidates_token_count: i32,
+    #[serde(default)]
+    pub total_token_count: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Content {
     pub role: String,
+    #[serde(default)]
     pub parts: Vec<Part>,
 }
 
@@ -37,32 +130,147 @@ pub struct Part {
     pub function_call: Option<FunctionCall>,
     #[serde(rename = "functionResponse")]
     pub function_response: Option<FunctionResponse>,
+    #[serde(rename = "inlineData", skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+    #[serde(rename = "fileData", skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<FileData>,
+    #[serde(rename = "executableCode", skip_serializing_if = "Option::is_none")]
+    pub executable_code: Option<ExecutableCode>,
+    #[serde(
+        rename = "codeExecutionResult",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub code_execution_result: Option<CodeExecutionResult>,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub thought: bool,
+    #[serde(rename = "thoughtSignature", skip_serializing_if = "Option::is_none")]
+    pub thought_signature: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExecutableCode {
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CodeExecutionResult {
+    #[serde(default)]
+    pub outcome: String,
+    #[serde(default)]
+    pub output: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    /// Base64 encoded bytes.
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    pub mime_type: String,
+    pub file_uri: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FunctionCall {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
-    pub args: HashMap<String, String>,
+    #[serde(default)]
+    pub args: Map<String, Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FunctionResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
-    pub response: String,
+    pub response: Value,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Candidate {
-    pub content: Content,
+    // Missing when the candidate is blocked.
+    pub content: Option<Content>,
     #[serde(rename = "finishReason")]
     pub finish_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub safety_ratings: Vec<SafetyRating>,
+    // Usually only on the last chunk of a grounded answer.
+    #[serde(rename = "groundingMetadata")]
+    pub grounding_metadata: Option<GroundingMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GroundingMetadata {
+    #[serde(default)]
+    pub web_search_queries: Vec<String>,
+    #[serde(default)]
+    pub grounding_chunks: Vec<GroundingChunk>,
+    #[serde(default)]
+    pub grounding_supports: Vec<GroundingSupport>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GroundingChunk {
+    pub web: Option<WebChunk>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebChunk {
+    #[serde(default)]
+    pub uri: String,
+    #[serde(default)]
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GroundingSupport {
+    pub segment: Segment,
+    #[serde(default)]
+    pub grounding_chunk_indices: Vec<i32>,
+    #[serde(default)]
+    pub confidence_scores: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Segment {
+    #[serde(default)]
+    pub part_index: i32,
+    #[serde(default)]
+    pub start_index: i32,
+    #[serde(default)]
+    pub end_index: i32,
+    #[serde(default)]
+    pub text: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Tool {
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub function_declarations: Vec<FunctionDeclaration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub google_search: Option<GoogleSearch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_execution: Option<CodeExecution>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GoogleSearch {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CodeExecution {}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FunctionDeclaration {
     pub name: String,
@@ -80,7 +288,20 @@ pub struct FunctionParameters {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FunctionParameter {
     pub r#type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nullable: bool,
+    #[serde(rename = "enum", default, skip_serializing_if = "Vec::is_empty")]
+    pub enum_values: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<FunctionParameter>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, FunctionParameter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
 }
 
 pub fn new_content_pb(role: String, text: String) -> ContentPb {
@@ -88,14 +309,21 @@ pub fn new_content_pb(role: String, text: String) -> ContentPb {
         role,
         parts: vec![PartPb {
             text: Some(text),
-            function_call: None,
-            function_response: None,
+            ..Default::default()
         }],
+        cancelled: false,
     }
 }
 
-pub const GENERATE_IMAGE: &str = "generate_image";
-pub const BRAVE_SEARCH: &str = "web_search";
+/// Content with an attachment (image, audio, PDF...) alongside the text.
+pub fn new_content_with_blobs_pb(role: String, text: String, blobs: Vec<BlobPb>) -> ContentPb {
+    let mut content = new_content_pb(role, text);
+    content.parts.extend(blobs.into_iter().map(|blob| PartPb {
+        inline_data: Some(blob),
+        ..Default::default()
+    }));
+    content
+}
 
 pub fn new_gemini_request_pb(contents: Vec<ContentPb>) -> GeminiRequestPb {
     GeminiRequestPb {
@@ -106,10 +334,155 @@ pub fn new_gemini_request_pb(contents: Vec<ContentPb>) -> GeminiRequestPb {
                     "You are Solus, an intelligent conversational assistant. Your primary goal is to engage in natural and helpful conversations with users. Do not include any prefix or identifier (like 'Solus:') at the beginning of your responses. Respond directly with the information or answer to the user's question."
                     .to_string(),
                 ),
-                function_call: None,
-                function_response: None,
+                ..Default::default()
             }],
         }),
         tools: vec![],
+        model: None,
+        generation_config: None,
+        safety_settings: vec![],
+        tool_config: None,
+        cached_content: None,
     }
 }
+
+/// Makes the model call `function_name` instead of answering. Composer goes back to
+/// letting the model decide once the call is answered.
+pub fn force_function_call(gemini_request_pb: &mut GeminiRequestPb, function_name: &str) {
+    gemini_request_pb.tool_config =
+        Some(new_tool_config_pb("ANY", vec![function_name.to_string()]));
+}
+
+/// Keeps the model from calling functions, it answers with text only.
+pub fn disable_function_calls(gemini_request_pb: &mut GeminiRequestPb) {
+    gemini_request_pb.tool_config = Some(new_tool_config_pb("NONE", vec![]));
+}
+
+/// Lets the model search Google and ground its answer in the results, the sources come
+/// back as `CandidatePb::grounding_metadata`. Composer leaves out function tools when it's on.
+pub fn enable_google_search(gemini_request_pb: &mut GeminiRequestPb) {
+    gemini_request_pb.tools.push(ToolPb {
+        google_search: Some(GoogleSearchPb {}),
+        ..Default::default()
+    });
+}
+
+/// Lets the model write and run Python, the code and its output come back as
+/// `PartPb::executable_code` and `PartPb::code_execution_result`.
+pub fn enable_code_execution(gemini_request_pb: &mut GeminiRequestPb) {
+    gemini_request_pb.tools.push(ToolPb {
+        code_execution: Some(CodeExecutionPb {}),
+        ..Default::default()
+    });
+}
+
+pub fn new_tool_config_pb(mode: &str, allowed_function_names: Vec<String>) -> ToolConfigPb {
+    ToolConfigPb {
+        function_calling_config: Some(FunctionCallingConfigPb {
+            mode: mode.into(),
+            allowed_function_names,
+        }),
+    }
+}
+
+/// Body of a failed request, e.g. `{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED", ...}}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiErrorResponse {
+    pub error: GeminiErrorDetails,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiErrorDetails {
+    #[serde(default)]
+    pub code: u16,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub status: String,
+    /// `google.rpc` details, e.g. a `RetryInfo` with `retryDelay: "37s"`.
+    #[serde(default)]
+    pub details: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CountTokensRequest {
+    pub contents: Vec<Content>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensResponse {
+    #[serde(default)]
+    pub total_tokens: i32,
+}
+
+/// One text to embed, `model` is required even inside a batch, e.g. `models/text-embedding-004`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbedContentRequest {
+    pub model: String,
+    pub content: Content,
+    /// e.g. `RETRIEVAL_DOCUMENT` or `RETRIEVAL_QUERY`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchEmbedContentsRequest {
+    pub requests: Vec<EmbedContentRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchEmbedContentsResponse {
+    #[serde(default)]
+    pub embeddings: Vec<ContentEmbedding>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContentEmbedding {
+    #[serde(default)]
+    pub values: Vec<f32>,
+}
+
+/// A cachedContents entry. `ttl` is only sent, the API answers with `expire_time` instead.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// e.g. `models/gemini-2.0-flash`, can't change once the cache exists.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<Content>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<SystemInstruction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// Seconds with an `s` suffix, e.g. `3600s`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing)]
+    pub expire_time: Option<String>,
+    #[serde(skip_serializing)]
+    pub usage_metadata: Option<CachedContentUsageMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedContentUsageMetadata {
+    #[serde(default)]
+    pub total_token_count: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListCachedContentsResponse {
+    #[serde(default)]
+    pub cached_contents: Vec<CachedContent>,
+    pub next_page_token: Option<String>,
+}
@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+
+use super::api::GeminiErrorResponse;
+
+/// A failed Gemini request, classified by HTTP status.
+#[derive(Debug)]
+pub enum GeminiError {
+    /// 429, the quota or rate limit was exceeded.
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// 500, 502, 503 or 504, Gemini is overloaded or having problems.
+    Unavailable {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// 400, e.g. an unsupported parameter or a malformed schema.
+    InvalidArgument {
+        message: String,
+    },
+    /// 401 or 403, the API key is missing, invalid or lacks access.
+    Unauthorized {
+        message: String,
+    },
+    /// 404, usually an unknown model.
+    NotFound {
+        message: String,
+    },
+    Http {
+        status: StatusCode,
+        message: String,
+    },
+    /// The connection could not be made or dropped.
+    Transport(reqwest::Error),
+    /// A streamed chunk was not a valid response.
+    InvalidResponse(String),
+}
+
+impl GeminiError {
+    /// Reads the status, `Retry-After` and error body of a failed response.
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after_header = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|retry_after| retry_after.to_str().ok())
+            .and_then(|retry_after| parse_retry_after(retry_after, SystemTime::now()));
+
+        let body = response.text().await.unwrap_or_default();
+        let (message, retry_delay) = match serde_json::from_str::<GeminiErrorResponse>(&body) {
+            Ok(error_response) => (
+                error_response.error.message,
+                error_response
+                    .error
+                    .details
+                    .iter()
+                    .filter_map(|detail| detail.get("retryDelay")?.as_str())
+                    .find_map(parse_retry_delay),
+            ),
+            Err(_) => (body, None),
+        };
+        let retry_after = retry_after_header.or(retry_delay);
+
+        match status.as_u16() {
+            429 => GeminiError::RateLimited {
+                message,
+                retry_after,
+            },
+            // Other 5xx, e.g. 501 Not Implemented, fail the same way every time.
+            500 | 502 | 503 | 504 => GeminiError::Unavailable {
+                status,
+                message,
+                retry_after,
+            },
+            400 => GeminiError::InvalidArgument { message },
+            401 | 403 => GeminiError::Unauthorized { message },
+            404 => GeminiError::NotFound { message },
+            _ => GeminiError::Http { status, message },
+        }
+    }
+
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            GeminiError::RateLimited { .. }
+                | GeminiError::Unavailable { .. }
+                | GeminiError::Transport(_)
+        )
+    }
+
+    /// How long Gemini asked us to wait before retrying, if it said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            GeminiError::RateLimited { retry_after, .. }
+            | GeminiError::Unavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeminiError::RateLimited { message, .. } => {
+                write!(f, "Gemini rate limit exceeded: {}", message)
+            }
+            GeminiError::Unavailable {
+                status, message, ..
+            } => write!(f, "Gemini is unavailable ({}): {}", status, message),
+            GeminiError::InvalidArgument { message } => {
+                write!(f, "Gemini rejected the request: {}", message)
+            }
+            GeminiError::Unauthorized { message } => {
+                write!(f, "Gemini rejected the API key: {}", message)
+            }
+            GeminiError::NotFound { message } => write!(f, "Gemini model not found: {}", message),
+            GeminiError::Http { status, message } => {
+                write!(f, "Gemini request failed ({}): {}", status, message)
+            }
+            GeminiError::Transport(e) => write!(f, "Could not reach Gemini: {}", e),
+            GeminiError::InvalidResponse(e) => write!(f, "Gemini sent an invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GeminiError::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// `Retry-After` is either seconds, e.g. "120", or an HTTP date, e.g.
+// "Wed, 21 Oct 2015 07:28:00 GMT". A date that has passed means now.
+fn parse_retry_after(retry_after: &str, now: SystemTime) -> Option<Duration> {
+    let retry_after = retry_after.trim();
+    if let Ok(seconds) = retry_after.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(retry_after).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+// `RetryInfo.retryDelay` is a protobuf Duration in JSON form, e.g. "37s" or "1.5s".
+fn parse_retry_delay(retry_delay: &str) -> Option<Duration> {
+    let seconds: f64 = retry_delay.strip_suffix('s')?.parse().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_is_read_in_whole_and_fractional_seconds() {
+        assert_eq!(parse_retry_delay("37s"), Some(Duration::from_secs(37)));
+        assert_eq!(parse_retry_delay("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_retry_delay("0s"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_delay_without_seconds_is_ignored() {
+        assert_eq!(parse_retry_delay("37"), None);
+        assert_eq!(parse_retry_delay("1m"), None);
+        assert_eq!(parse_retry_delay("s"), None);
+        assert_eq!(parse_retry_delay("-1s"), None);
+        assert_eq!(parse_retry_delay(""), None);
+    }
+
+    #[test]
+    fn retry_after_is_read_as_seconds() {
+        let now = SystemTime::now();
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 5 ", now), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_after_is_read_as_an_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn retry_after_that_is_neither_is_ignored() {
+        assert_eq!(parse_retry_after("soon", SystemTime::now()), None);
+    }
+}
@@ -1,151 +1,464 @@
 pub mod api;
+pub mod error;
+pub mod structured;
 
 use crate::proto::message::{
-    CandidatePb, ContentPb, FunctionCallPb, FunctionDeclarationPb, FunctionParameterPb,
-    FunctionParametersPb, FunctionResponsePb, GeminiRequestPb, GeminiResponsePb, PartPb,
-    SystemInstructionPb, ToolPb,
+    BlobPb, CachedContentPb, CandidatePb, CodeExecutionResultPb, ContentPb, ExecutableCodePb,
+    FileDataPb, FunctionCallPb, FunctionDeclarationPb, FunctionParameterPb, FunctionParametersPb,
+    FunctionResponsePb, GeminiRequestPb, GeminiResponsePb, GenerationConfigPb, GroundingChunkPb,
+    GroundingMetadataPb, GroundingSupportPb, PartPb, PromptFeedbackPb, SafetyRatingPb, SegmentPb,
+    SystemInstructionPb, ToolConfigPb, ToolPb, UsageMetadataPb, WebChunkPb,
 };
-use anyhow::{bail, Result};
+use anyhow::Result;
 use api::{
-    Candidate, Content, FunctionCall, FunctionDeclaration, FunctionParameter, FunctionParameters,
-    FunctionResponse, GeminiRequest, GeminiResponse, Part, SystemInstruction, Tool,
+    new_content_pb, BatchEmbedContentsRequest, BatchEmbedContentsResponse, Blob, CachedContent,
+    Candidate, CodeExecution, CodeExecutionResult, Content, CountTokensRequest,
+    CountTokensResponse, EmbedContentRequest, ExecutableCode, FileData, FunctionCall,
+    FunctionCallingConfig, FunctionDeclaration, FunctionParameter, FunctionParameters,
+    FunctionResponse, GeminiRequest, GeminiResponse, GenerationConfig, GoogleSearch,
+    GroundingMetadata, ListCachedContentsResponse, Part, PromptFeedback, SafetyRating,
+    SafetySetting, SystemInstruction, ThinkingConfig, Tool, ToolConfig, UsageMetadata,
 };
-use reqwest_eventsource::{Error::StreamEnded, Event, EventSource};
+use async_trait::async_trait;
+use base64::prelude::*;
+use error::GeminiError;
+use rand::Rng;
+use reqwest::{Client, RequestBuilder};
+use reqwest_eventsource::{
+    Error::{self as EventSourceError, StreamEnded},
+    Event, EventSource,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
 
-use crate::data::{self, CommandData};
+use crate::llm::{EmbeddingTask, LlmProvider};
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, env, time::Duration};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_stream::StreamExt;
 
-pub async fn invoke(
-    command_data: Arc<CommandData>,
-    session_id: &String,
-    gemini_request_pb: &GeminiRequestPb,
-    sender: UnboundedSender<GeminiResponsePb>,
-) -> Result<()> {
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-flash:streamGenerateContent?alt=sse&key={}",
-        &command_data.gemini_token
-    );
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+// Longer waits (e.g. a daily quota) are reported instead of retried.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(32);
+// Most requests batchEmbedContents accepts at once.
+const MAX_EMBED_BATCH: usize = 100;
+
+// Finish reasons that mean the response was cut off by a filter rather than completed.
+pub(crate) const BLOCKED_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+
+/// Where requests are sent. `GeminiRequestPb.model` overrides `model` per request.
+#[derive(Debug, Clone)]
+pub struct GeminiConfig {
+    pub base_url: String,
+    pub api_version: String,
+    pub model: String,
+    /// Model for `embed`, separate from the generation model.
+    pub embedding_model: String,
+    /// Retries for rate limited, unavailable or dropped requests, 0 disables them.
+    pub max_retries: u32,
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://generativelanguage.googleapis.com".into(),
+            api_version: "v1beta".into(),
+            model: "gemini-2.0-flash".into(),
+            embedding_model: "text-embedding-004".into(),
+            max_retries: 3,
+        }
+    }
+}
+
+impl GeminiConfig {
+    /// Defaults overridden by `GEMINI_BASE_URL`, `GEMINI_API_VERSION`, `GEMINI_MODEL`,
+    /// `GEMINI_EMBEDDING_MODEL` and `GEMINI_MAX_RETRIES`.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            base_url: env::var("GEMINI_BASE_URL").unwrap_or(default.base_url),
+            api_version: env::var("GEMINI_API_VERSION").unwrap_or(default.api_version),
+            model: env::var("GEMINI_MODEL").unwrap_or(default.model),
+            embedding_model: env::var("GEMINI_EMBEDDING_MODEL").unwrap_or(default.embedding_model),
+            max_retries: env::var("GEMINI_MAX_RETRIES")
+                .ok()
+                .and_then(|max_retries| max_retries.parse().ok())
+                .unwrap_or(default.max_retries),
+        }
+    }
 
-    let new_content = &gemini_request_pb.contents[0];
+    /// URL of a model method, e.g. `streamGenerateContent`.
+    pub fn model_url(&self, model: &str, method: &str) -> String {
+        format!(
+            "{}/{}/models/{}:{}",
+            self.base_url.trim_end_matches('/'),
+            self.api_version,
+            model,
+            method
+        )
+    }
 
-    data::add_content(&command_data, session_id, new_content).await?;
+    /// URL of the cachedContents collection, or of one cache by its name (`cachedContents/...`).
+    pub fn cached_contents_url(&self, name: Option<&str>) -> String {
+        let base_url = self.base_url.trim_end_matches('/');
+        match name {
+            Some(name) => format!("{}/{}/{}", base_url, self.api_version, name),
+            None => format!("{}/{}/cachedContents", base_url, self.api_version),
+        }
+    }
+}
 
-    let contents = data::get_content(&command_data, session_id).await?;
+pub struct GeminiProvider {
+    reqwest_client: Client,
+    gemini_token: String,
+    config: GeminiConfig,
+}
 
-    let gemini_request: GeminiRequest = GeminiRequest {
-        contents: contents.iter().map(content_from_pb).collect(),
-        tools: gemini_request_pb.tools.iter().map(tool_from_pb).collect(),
-        system_instruction: system_instruction_from_pb(
-            gemini_request_pb.system_instruction.as_ref(),
-        ),
-    };
-
-    let request_builder = command_data
-        .reqwest_client
-        .post(url)
-        .header("Content-Type", "application/json")
-        .json(&gemini_request);
-
-    let mut es = EventSource::new(request_builder)?;
-    while let Some(event) = es.next().await {
-        match event {
-            Ok(Event::Message(message)) => {
-                let gemini_response: GeminiResponse = match serde_json::from_str(&message.data) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        bail!("GeminiResponse: {}", e)
-                    }
-                };
-
-                let gemini_response_pb = pb_from_gemini_response(&gemini_response);
-
-                let model_content = match &gemini_response_pb.candidates[0].content {
-                    Some(content) => content,
-                    None => {
-                        continue;
-                    }
-                };
-
-                // if response has text, only save it if not empty
-                // else, save always
-                if model_content.parts[0]
-                    .text
-                    .as_ref()
-                    .map_or(true, |t| !t.is_empty())
+impl GeminiProvider {
+    pub fn new(reqwest_client: Client, gemini_token: String, config: GeminiConfig) -> Self {
+        Self {
+            reqwest_client,
+            gemini_token,
+            config,
+        }
+    }
+
+    async fn stream_once(
+        &self,
+        url: &str,
+        gemini_request: &GeminiRequest,
+        sender: &UnboundedSender<GeminiResponsePb>,
+        forwarded: &mut bool,
+    ) -> Result<()> {
+        let request_builder = self
+            .reqwest_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .json(gemini_request);
+
+        let mut es = EventSource::new(request_builder)?;
+        while let Some(event) = es.next().await {
+            match event {
+                Ok(Event::Message(message)) => {
+                    let gemini_response: GeminiResponse = match serde_json::from_str(&message.data)
+                    {
+                        Ok(v) => v,
+                        Err(e) => return Err(GeminiError::InvalidResponse(e.to_string()).into()),
+                    };
+                    let gemini_response_pb = pb_from_gemini_response(&gemini_response);
+                    sender.send(gemini_response_pb)?;
+                    *forwarded = true;
+                }
+                Err(StreamEnded) => es.close(),
+                Err(EventSourceError::InvalidStatusCode(_, response))
+                | Err(EventSourceError::InvalidContentType(_, response)) => {
+                    return Err(GeminiError::from_response(response).await.into())
+                }
+                Err(EventSourceError::Transport(e)) => return Err(GeminiError::Transport(e).into()),
+                Err(err) => return Err(GeminiError::InvalidResponse(err.to_string()).into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sends a cachedContents request and parses its response.
+    async fn send_cache_request<T: DeserializeOwned>(
+        &self,
+        request_builder: RequestBuilder,
+    ) -> Result<T> {
+        let response = request_builder
+            .query(&[("key", &self.gemini_token)])
+            .send()
+            .await
+            .map_err(GeminiError::Transport)?;
+
+        if !response.status().is_success() {
+            return Err(GeminiError::from_response(response).await.into());
+        }
+
+        Ok(response.json().await.map_err(GeminiError::Transport)?)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    async fn stream_generate(
+        &self,
+        gemini_request_pb: &GeminiRequestPb,
+        sender: UnboundedSender<GeminiResponsePb>,
+    ) -> Result<()> {
+        let model = gemini_request_pb
+            .model
+            .as_deref()
+            .unwrap_or(&self.config.model);
+        let url = format!(
+            "{}?alt=sse&key={}",
+            self.config.model_url(model, "streamGenerateContent"),
+            &self.gemini_token
+        );
+
+        let gemini_request: GeminiRequest = GeminiRequest {
+            contents: gemini_request_pb
+                .contents
+                .iter()
+                .map(content_from_pb)
+                .collect(),
+            tools: gemini_request_pb.tools.iter().map(tool_from_pb).collect(),
+            system_instruction: system_instruction_from_pb(
+                gemini_request_pb.system_instruction.as_ref(),
+            ),
+            generation_config: gemini_request_pb
+                .generation_config
+                .as_ref()
+                .map(generation_config_from_pb),
+            safety_settings: gemini_request_pb
+                .safety_settings
+                .iter()
+                .map(|safety_setting_pb| SafetySetting {
+                    category: safety_setting_pb.category.clone(),
+                    threshold: safety_setting_pb.threshold.clone(),
+                })
+                .collect(),
+            tool_config: request_tool_config(gemini_request_pb),
+            cached_content: gemini_request_pb.cached_content.clone(),
+        };
+
+        let mut attempt = 0;
+        loop {
+            let mut forwarded = false;
+            let error = match self
+                .stream_once(&url, &gemini_request, &sender, &mut forwarded)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+
+            // Once chunks were forwarded a retry would send them twice.
+            let delay = match error.downcast_ref::<GeminiError>() {
+                Some(gemini_error)
+                    if !forwarded
+                        && gemini_error.is_retryable()
+                        && attempt < self.config.max_retries =>
                 {
-                    data::add_content(&command_data, session_id, model_content).await?;
+                    retry_delay(attempt, gemini_error.retry_after())
                 }
+                _ => None,
+            };
+
+            match delay {
+                Some(delay) => {
+                    println!("{}, retrying in {:?}", error, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(error),
+            }
+        }
+    }
+
+    async fn count_tokens(&self, model: Option<&str>, contents: &[ContentPb]) -> Result<i32> {
+        let model = model.unwrap_or(&self.config.model);
+        let url = format!(
+            "{}?key={}",
+            self.config.model_url(model, "countTokens"),
+            &self.gemini_token
+        );
+
+        let response = self
+            .reqwest_client
+            .post(url)
+            .json(&CountTokensRequest {
+                contents: contents.iter().map(content_from_pb).collect(),
+            })
+            .send()
+            .await
+            .map_err(GeminiError::Transport)?;
+
+        if !response.status().is_success() {
+            return Err(GeminiError::from_response(response).await.into());
+        }
 
-                sender.send(gemini_response_pb)?;
+        let count_tokens_response: CountTokensResponse =
+            response.json().await.map_err(GeminiError::Transport)?;
+
+        Ok(count_tokens_response.total_tokens)
+    }
+
+    async fn embed(&self, texts: &[String], task: EmbeddingTask) -> Result<Vec<Vec<f32>>> {
+        let url = format!(
+            "{}?key={}",
+            self.config
+                .model_url(&self.config.embedding_model, "batchEmbedContents"),
+            &self.gemini_token
+        );
+        let task_type = match task {
+            EmbeddingTask::Document => "RETRIEVAL_DOCUMENT",
+            EmbeddingTask::Query => "RETRIEVAL_QUERY",
+        };
+
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(MAX_EMBED_BATCH) {
+            let batch_request = BatchEmbedContentsRequest {
+                requests: batch
+                    .iter()
+                    .map(|text| EmbedContentRequest {
+                        model: format!("models/{}", self.config.embedding_model),
+                        content: content_from_pb(&new_content_pb("user".into(), text.clone())),
+                        task_type: Some(task_type.into()),
+                    })
+                    .collect(),
+            };
+
+            let response = self
+                .reqwest_client
+                .post(&url)
+                .json(&batch_request)
+                .send()
+                .await
+                .map_err(GeminiError::Transport)?;
+
+            if !response.status().is_success() {
+                return Err(GeminiError::from_response(response).await.into());
             }
-            Err(err) => {
-                match err {
-                    StreamEnded => {}
-                    _ => bail!("EventSource: {}", err),
-                }
-                es.close();
+
+            let batch_response: BatchEmbedContentsResponse =
+                response.json().await.map_err(GeminiError::Transport)?;
+            if batch_response.embeddings.len() != batch.len() {
+                return Err(GeminiError::InvalidResponse(format!(
+                    "expected {} embeddings, got {}",
+                    batch.len(),
+                    batch_response.embeddings.len()
+                ))
+                .into());
             }
-            _ => {}
+
+            embeddings.extend(
+                batch_response
+                    .embeddings
+                    .into_iter()
+                    .map(|embedding| embedding.values),
+            );
         }
+
+        Ok(embeddings)
     }
 
-    Ok(())
-}
+    fn supports_caching(&self) -> bool {
+        true
+    }
 
-pub async fn invoke_simple(
-    command_data: Arc<CommandData>,
-    gemini_request_pb: &GeminiRequestPb,
-    sender: UnboundedSender<GeminiResponsePb>,
-) -> Result<()> {
-    let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse&key={}",
-        &command_data.gemini_token
-    );
+    async fn create_cache(
+        &self,
+        gemini_request_pb: &GeminiRequestPb,
+        ttl: Duration,
+    ) -> Result<CachedContentPb> {
+        let model = gemini_request_pb
+            .model
+            .as_deref()
+            .unwrap_or(&self.config.model);
+        let cached_content = CachedContent {
+            model: format!("models/{}", model),
+            contents: gemini_request_pb
+                .contents
+                .iter()
+                .map(content_from_pb)
+                .collect(),
+            tools: gemini_request_pb.tools.iter().map(tool_from_pb).collect(),
+            system_instruction: system_instruction_from_pb(
+                gemini_request_pb.system_instruction.as_ref(),
+            ),
+            tool_config: request_tool_config(gemini_request_pb),
+            ttl: Some(ttl_from_duration(ttl)),
+            display_name: Some("solus".into()),
+            ..Default::default()
+        };
+
+        let request_builder = self
+            .reqwest_client
+            .post(self.config.cached_contents_url(None))
+            .json(&cached_content);
+        let cached_content: CachedContent = self.send_cache_request(request_builder).await?;
+
+        Ok(pb_from_cached_content(&cached_content))
+    }
 
-    let contents = &gemini_request_pb.contents;
+    async fn list_caches(&self) -> Result<Vec<CachedContentPb>> {
+        let mut caches = vec![];
+        let mut page_token: Option<String> = None;
 
-    let gemini_request: GeminiRequest = GeminiRequest {
-        contents: contents.iter().map(content_from_pb).collect(),
-        tools: gemini_request_pb.tools.iter().map(tool_from_pb).collect(),
-        system_instruction: system_instruction_from_pb(
-            gemini_request_pb.system_instruction.as_ref(),
-        ),
-    };
-
-    let request_builder = command_data
-        .reqwest_client
-        .post(url)
-        .header("Content-Type", "application/json")
-        .json(&gemini_request);
-
-    let mut es = EventSource::new(request_builder)?;
-    while let Some(event) = es.next().await {
-        match event {
-            Ok(Event::Message(message)) => {
-                let gemini_response: GeminiResponse = match serde_json::from_str(&message.data) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        bail!("GeminiResponse: {}", e)
-                    }
-                };
-                let gemini_response_pb = pb_from_gemini_response(&gemini_response);
-                sender.send(gemini_response_pb)?;
+        loop {
+            let mut request_builder = self
+                .reqwest_client
+                .get(self.config.cached_contents_url(None));
+            if let Some(page_token) = &page_token {
+                request_builder = request_builder.query(&[("pageToken", page_token)]);
             }
-            Err(err) => {
-                match err {
-                    StreamEnded => {}
-                    _ => bail!("EventSource: {}", err),
+
+            let list_response: ListCachedContentsResponse =
+                self.send_cache_request(request_builder).await?;
+            caches.extend(
+                list_response
+                    .cached_contents
+                    .iter()
+                    .map(pb_from_cached_content),
+            );
+
+            match list_response.next_page_token {
+                Some(next_page_token) if !next_page_token.is_empty() => {
+                    page_token = Some(next_page_token)
                 }
-                es.close();
+                _ => return Ok(caches),
             }
-            _ => {}
         }
     }
 
-    Ok(())
+    async fn update_cache_ttl(&self, name: &str, ttl: Duration) -> Result<CachedContentPb> {
+        let request_builder = self
+            .reqwest_client
+            .patch(self.config.cached_contents_url(Some(name)))
+            .query(&[("updateMask", "ttl")])
+            .json(&CachedContent {
+                ttl: Some(ttl_from_duration(ttl)),
+                ..Default::default()
+            });
+        let cached_content: CachedContent = self.send_cache_request(request_builder).await?;
+
+        Ok(pb_from_cached_content(&cached_content))
+    }
+
+    async fn delete_cache(&self, name: &str) -> Result<()> {
+        let request_builder = self
+            .reqwest_client
+            .delete(self.config.cached_contents_url(Some(name)));
+        let _: Value = self.send_cache_request(request_builder).await?;
+
+        Ok(())
+    }
+}
+
+// Honors the server's requested delay, otherwise backs off exponentially with jitter.
+fn retry_delay(attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+    match retry_after {
+        Some(retry_after) if retry_after > MAX_RETRY_DELAY => None,
+        Some(retry_after) => Some(retry_after),
+        None => {
+            let delay = RETRY_BASE_DELAY
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(MAX_RETRY_DELAY);
+            // Spread out clients that failed at the same time.
+            Some(delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)))
+        }
+    }
 }
 
 // Don't need this?
@@ -159,15 +472,59 @@ pub async fn invoke_simple(
 fn system_instruction_from_pb(
     system_instruction_pb: Option<&SystemInstructionPb>,
 ) -> Option<SystemInstruction> {
-    match system_instruction_pb {
-        None => None,
-        Some(system_instruction_pb) => Some(SystemInstruction {
-            parts: system_instruction_pb
-                .parts
+    system_instruction_pb.map(|system_instruction_pb| SystemInstruction {
+        parts: system_instruction_pb
+            .parts
+            .iter()
+            .map(part_from_pb)
+            .collect(),
+    })
+}
+
+// Gemini rejects a function calling config without function declarations.
+fn request_tool_config(gemini_request_pb: &GeminiRequestPb) -> Option<ToolConfig> {
+    gemini_request_pb
+        .tool_config
+        .as_ref()
+        .filter(|_| {
+            gemini_request_pb
+                .tools
                 .iter()
-                .map(part_from_pb)
-                .collect(),
-        }),
+                .any(|tool_pb| !tool_pb.function_declarations.is_empty())
+        })
+        .and_then(tool_config_from_pb)
+}
+
+fn tool_config_from_pb(tool_config_pb: &ToolConfigPb) -> Option<ToolConfig> {
+    let function_calling_config_pb = tool_config_pb.function_calling_config.as_ref()?;
+
+    Some(ToolConfig {
+        function_calling_config: FunctionCallingConfig {
+            mode: function_calling_config_pb.mode.clone(),
+            allowed_function_names: function_calling_config_pb.allowed_function_names.clone(),
+        },
+    })
+}
+
+fn generation_config_from_pb(generation_config_pb: &GenerationConfigPb) -> GenerationConfig {
+    GenerationConfig {
+        temperature: generation_config_pb.temperature,
+        top_p: generation_config_pb.top_p,
+        top_k: generation_config_pb.top_k,
+        max_output_tokens: generation_config_pb.max_output_tokens,
+        stop_sequences: generation_config_pb.stop_sequences.clone(),
+        response_mime_type: generation_config_pb.response_mime_type.clone(),
+        response_schema: generation_config_pb
+            .response_schema
+            .as_ref()
+            .map(schema_from_pb),
+        thinking_config: generation_config_pb
+            .thinking_config
+            .as_ref()
+            .map(|thinking_config_pb| ThinkingConfig {
+                thinking_budget: thinking_config_pb.thinking_budget,
+                include_thoughts: thinking_config_pb.include_thoughts,
+            }),
     }
 }
 
@@ -183,29 +540,54 @@ fn part_from_pb(part_pb: &PartPb) -> Part {
         text: part_pb.text.clone(),
         function_call: function_call_from_pb(part_pb.function_call.as_ref()),
         function_response: function_response_from_pb(part_pb.function_response.as_ref()),
+        inline_data: part_pb.inline_data.as_ref().map(|blob_pb| Blob {
+            mime_type: blob_pb.mime_type.clone(),
+            data: BASE64_STANDARD.encode(&blob_pb.data),
+        }),
+        file_data: part_pb.file_data.as_ref().map(|file_data_pb| FileData {
+            mime_type: file_data_pb.mime_type.clone(),
+            file_uri: file_data_pb.file_uri.clone(),
+        }),
+        executable_code: part_pb.executable_code.as_ref().map(|executable_code_pb| {
+            ExecutableCode {
+                language: executable_code_pb.language.clone(),
+                code: executable_code_pb.code.clone(),
+            }
+        }),
+        code_execution_result: part_pb.code_execution_result.as_ref().map(
+            |code_execution_result_pb| CodeExecutionResult {
+                outcome: code_execution_result_pb.outcome.clone(),
+                output: code_execution_result_pb.output.clone(),
+            },
+        ),
+        thought: part_pb.thought,
+        thought_signature: part_pb.thought_signature.clone(),
     }
 }
 
 fn function_call_from_pb(function_call_pb: Option<&FunctionCallPb>) -> Option<FunctionCall> {
-    match function_call_pb {
-        None => None,
-        Some(function_call_pb) => Some(FunctionCall {
-            name: function_call_pb.name.clone(),
-            args: function_call_pb.args.clone(),
-        }),
-    }
+    function_call_pb.map(|function_call_pb| FunctionCall {
+        id: function_call_pb.id.clone(),
+        name: function_call_pb.name.clone(),
+        args: function_call_pb.args().unwrap_or_default(),
+    })
 }
 
+// Gemini expects the function response to be a JSON object, so wrap anything else.
 fn function_response_from_pb(
     function_response_pb: Option<&FunctionResponsePb>,
 ) -> Option<FunctionResponse> {
-    match function_response_pb {
-        None => None,
-        Some(function_response_pb) => Some(FunctionResponse {
-            name: function_response_pb.name.clone(),
-            response: function_response_pb.response.clone(),
-        }),
-    }
+    function_response_pb.map(|function_response_pb| FunctionResponse {
+        id: function_response_pb.id.clone(),
+        name: function_response_pb.name.clone(),
+        response: match function_response_pb.response().unwrap_or_default() {
+            Value::Object(response) => Value::Object(response),
+            response => json!({
+                "name": function_response_pb.name,
+                "content": response,
+            }),
+        },
+    })
 }
 
 fn tool_from_pb(tool_pb: &ToolPb) -> Tool {
@@ -215,10 +597,12 @@ fn tool_from_pb(tool_pb: &ToolPb) -> Tool {
             .iter()
             .map(function_declaration_from_pb)
             .collect(),
+        google_search: tool_pb.google_search.as_ref().map(|_| GoogleSearch {}),
+        code_execution: tool_pb.code_execution.as_ref().map(|_| CodeExecution {}),
     }
 }
 
-fn function_declaration_from_pb(
+pub(crate) fn function_declaration_from_pb(
     function_declaration_pb: &FunctionDeclarationPb,
 ) -> FunctionDeclaration {
     let parameters = function_declaration_pb
@@ -228,7 +612,7 @@ fn function_declaration_from_pb(
     FunctionDeclaration {
         name: function_declaration_pb.name.clone(),
         description: function_declaration_pb.description.clone(),
-        parameters: function_parameters_from_pb(&parameters),
+        parameters: function_parameters_from_pb(parameters),
     }
 }
 
@@ -247,18 +631,44 @@ fn function_parameter_from_pb(
 ) -> HashMap<String, FunctionParameter> {
     function_parameter_pb
         .iter()
-        .map(|(k, v)| {
-            (
-                k.clone(),
-                FunctionParameter {
-                    r#type: v.r#type.clone(),
-                    description: v.description.clone(),
-                },
-            )
-        })
+        .map(|(k, v)| (k.clone(), schema_from_pb(v)))
         .collect()
 }
 
+pub(crate) fn schema_from_pb(function_parameter_pb: &FunctionParameterPb) -> FunctionParameter {
+    FunctionParameter {
+        r#type: function_parameter_pb.r#type.clone(),
+        description: function_parameter_pb.description.clone(),
+        format: function_parameter_pb.format.clone(),
+        nullable: function_parameter_pb.nullable,
+        enum_values: function_parameter_pb.enum_values.clone(),
+        items: function_parameter_pb
+            .items
+            .as_ref()
+            .map(|items| Box::new(schema_from_pb(items))),
+        properties: function_parameter_from_pb(&function_parameter_pb.properties),
+        required: function_parameter_pb.required.clone(),
+    }
+}
+
+// The API takes durations as seconds with an `s` suffix.
+fn ttl_from_duration(ttl: Duration) -> String {
+    format!("{}s", ttl.as_secs())
+}
+
+fn pb_from_cached_content(cached_content: &CachedContent) -> CachedContentPb {
+    CachedContentPb {
+        name: cached_content.name.clone().unwrap_or_default(),
+        model: cached_content.model.clone(),
+        display_name: cached_content.display_name.clone().unwrap_or_default(),
+        expire_time: cached_content.expire_time.clone().unwrap_or_default(),
+        total_token_count: cached_content
+            .usage_metadata
+            .as_ref()
+            .map_or(0, |usage_metadata| usage_metadata.total_token_count),
+    }
+}
+
 fn pb_from_gemini_response(gemini_response: &GeminiResponse) -> GeminiResponsePb {
     GeminiResponsePb {
         candidates: gemini_response
@@ -266,21 +676,96 @@ fn pb_from_gemini_response(gemini_response: &GeminiResponse) -> GeminiResponsePb
             .iter()
             .map(pb_from_candidate)
             .collect(),
+        usage_metadata: gemini_response
+            .usage_metadata
+            .as_ref()
+            .map(pb_from_usage_metadata),
+        prompt_feedback: gemini_response
+            .prompt_feedback
+            .as_ref()
+            .map(pb_from_prompt_feedback),
+    }
+}
+
+fn pb_from_usage_metadata(usage_metadata: &UsageMetadata) -> UsageMetadataPb {
+    UsageMetadataPb {
+        prompt_token_count: usage_metadata.prompt_token_count.into(),
+        candidates_token_count: usage_metadata.candidates_token_count.into(),
+        total_token_count: usage_metadata.total_token_count.into(),
     }
 }
 
 fn pb_from_candidate(candidate: &Candidate) -> CandidatePb {
     CandidatePb {
-        content: pb_from_content(&candidate.content),
+        content: candidate.content.as_ref().map(pb_from_content),
         finish_reason: candidate.finish_reason.clone(),
+        safety_ratings: candidate
+            .safety_ratings
+            .iter()
+            .map(pb_from_safety_rating)
+            .collect(),
+        grounding_metadata: candidate
+            .grounding_metadata
+            .as_ref()
+            .map(pb_from_grounding_metadata),
+    }
+}
+
+fn pb_from_grounding_metadata(grounding_metadata: &GroundingMetadata) -> GroundingMetadataPb {
+    GroundingMetadataPb {
+        web_search_queries: grounding_metadata.web_search_queries.clone(),
+        grounding_chunks: grounding_metadata
+            .grounding_chunks
+            .iter()
+            .map(|grounding_chunk| GroundingChunkPb {
+                web: grounding_chunk.web.as_ref().map(|web| WebChunkPb {
+                    uri: web.uri.clone(),
+                    title: web.title.clone(),
+                }),
+            })
+            .collect(),
+        grounding_supports: grounding_metadata
+            .grounding_supports
+            .iter()
+            .map(|grounding_support| GroundingSupportPb {
+                segment: Some(SegmentPb {
+                    part_index: grounding_support.segment.part_index,
+                    start_index: grounding_support.segment.start_index,
+                    end_index: grounding_support.segment.end_index,
+                    text: grounding_support.segment.text.clone(),
+                }),
+                grounding_chunk_indices: grounding_support.grounding_chunk_indices.clone(),
+                confidence_scores: grounding_support.confidence_scores.clone(),
+            })
+            .collect(),
     }
 }
 
-fn pb_from_content(content: &Content) -> Option<ContentPb> {
-    Some(ContentPb {
+fn pb_from_prompt_feedback(prompt_feedback: &PromptFeedback) -> PromptFeedbackPb {
+    PromptFeedbackPb {
+        block_reason: prompt_feedback.block_reason.clone(),
+        safety_ratings: prompt_feedback
+            .safety_ratings
+            .iter()
+            .map(pb_from_safety_rating)
+            .collect(),
+    }
+}
+
+fn pb_from_safety_rating(safety_rating: &SafetyRating) -> SafetyRatingPb {
+    SafetyRatingPb {
+        category: safety_rating.category.clone(),
+        probability: safety_rating.probability.clone(),
+        blocked: safety_rating.blocked,
+    }
+}
+
+fn pb_from_content(content: &Content) -> ContentPb {
+    ContentPb {
         role: content.role.clone(),
         parts: content.parts.iter().map(pb_from_part).collect(),
-    })
+        cancelled: false,
+    }
 }
 
 fn pb_from_part(part: &Part) -> PartPb {
@@ -288,27 +773,52 @@ fn pb_from_part(part: &Part) -> PartPb {
         text: part.text.clone(),
         function_call: pb_from_function_call(part.function_call.as_ref()),
         function_response: pb_from_function_response(part.function_response.as_ref()),
+        // Undecodable data is dropped rather than failing the whole stream.
+        inline_data: part.inline_data.as_ref().and_then(|blob| {
+            Some(BlobPb {
+                mime_type: blob.mime_type.clone(),
+                data: BASE64_STANDARD.decode(&blob.data).ok()?,
+            })
+        }),
+        file_data: part.file_data.as_ref().map(|file_data| FileDataPb {
+            mime_type: file_data.mime_type.clone(),
+            file_uri: file_data.file_uri.clone(),
+        }),
+        executable_code: part
+            .executable_code
+            .as_ref()
+            .map(|executable_code| ExecutableCodePb {
+                language: executable_code.language.clone(),
+                code: executable_code.code.clone(),
+            }),
+        code_execution_result: part
+            .code_execution_result
+            .as_ref()
+            .map(|code_execution_result| CodeExecutionResultPb {
+                outcome: code_execution_result.outcome.clone(),
+                output: code_execution_result.output.clone(),
+            }),
+        thought: part.thought,
+        thought_signature: part.thought_signature.clone(),
     }
 }
 
 fn pb_from_function_call(function_call: Option<&FunctionCall>) -> Option<FunctionCallPb> {
-    match function_call {
-        None => None,
-        Some(function_call) => Some(FunctionCallPb {
-            name: function_call.name.clone(),
-            args: function_call.args.clone(),
-        }),
-    }
+    function_call.map(|function_call| FunctionCallPb {
+        id: function_call.id.clone(),
+        name: function_call.name.clone(),
+        args_json: Value::Object(function_call.args.clone()).to_string(),
+        ..Default::default()
+    })
 }
 
 fn pb_from_function_response(
     function_response: Option<&FunctionResponse>,
 ) -> Option<FunctionResponsePb> {
-    match function_response {
-        None => None,
-        Some(function_response) => Some(FunctionResponsePb {
-            name: function_response.name.clone(),
-            response: function_response.response.clone(),
-        }),
-    }
+    function_response.map(|function_response| FunctionResponsePb {
+        id: function_response.id.clone(),
+        name: function_response.name.clone(),
+        response_json: function_response.response.to_string(),
+        ..Default::default()
+    })
 }
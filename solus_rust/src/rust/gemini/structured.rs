@@ -0,0 +1,329 @@
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::{
+    gemini::BLOCKED_FINISH_REASONS,
+    llm::LlmProvider,
+    proto::message::{GeminiRequestPb, GenerationConfigPb},
+    tools::schema::parameter_schema,
+};
+
+#[derive(Debug)]
+pub enum GenerateJsonError {
+    /// The request itself failed.
+    Request(anyhow::Error),
+    /// The prompt or the response was rejected by a safety filter.
+    Blocked(String),
+    /// The model answered, but not with JSON matching `T`.
+    Parse {
+        text: String,
+        source: serde_json::Error,
+    },
+}
+
+impl fmt::Display for GenerateJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateJsonError::Request(e) => write!(f, "Request failed: {}", e),
+            GenerateJsonError::Blocked(reason) => write!(f, "Response was blocked: {}", reason),
+            GenerateJsonError::Parse { source, .. } => {
+                write!(f, "Response did not match the schema: {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateJsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GenerateJsonError::Request(e) => Some(e.as_ref()),
+            GenerateJsonError::Blocked(_) => None,
+            GenerateJsonError::Parse { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Generates a response constrained to `T`'s schema and deserializes it.
+/// Nothing is loaded from or saved to a session.
+pub async fn generate_json<T: DeserializeOwned + JsonSchema>(
+    llm_provider: &dyn LlmProvider,
+    gemini_request_pb: &GeminiRequestPb,
+) -> Result<T, GenerateJsonError> {
+    let mut gemini_request_pb = gemini_request_pb.clone();
+    let generation_config = gemini_request_pb
+        .generation_config
+        .get_or_insert_with(GenerationConfigPb::default);
+    generation_config.response_mime_type = Some("application/json".into());
+    generation_config.response_schema = Some(parameter_schema::<T>());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let generate = llm_provider.stream_generate(&gemini_request_pb, tx);
+
+    let collect = async {
+        let mut text = String::new();
+        let mut blocked = None;
+
+        while let Some(gemini_response_pb) = rx.recv().await {
+            if let Some(block_reason) = gemini_response_pb
+                .prompt_feedback
+                .and_then(|prompt_feedback| prompt_feedback.block_reason)
+            {
+                blocked = Some(block_reason);
+            }
+
+            for candidate in gemini_response_pb.candidates {
+                if let Some(finish_reason) = candidate.finish_reason.filter(|finish_reason| {
+                    BLOCKED_FINISH_REASONS.contains(&finish_reason.as_str())
+                }) {
+                    blocked = Some(finish_reason);
+                }
+
+                let parts = candidate.content.map(|content| content.parts);
+                for part in parts.into_iter().flatten() {
+                    if let Some(part_text) = part.answer_text() {
+                        text.push_str(part_text);
+                    }
+                }
+            }
+        }
+
+        (text, blocked)
+    };
+
+    let (generated, (text, blocked)) = tokio::join!(generate, collect);
+    generated.map_err(GenerateJsonError::Request)?;
+
+    if let Some(reason) = blocked {
+        return Err(GenerateJsonError::Blocked(reason));
+    }
+
+    serde_json::from_str(&text).map_err(|source| GenerateJsonError::Parse { text, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::*;
+    use crate::proto::message::{
+        CandidatePb, ContentPb, GeminiResponsePb, PartPb, PromptFeedbackPb,
+    };
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Answer {
+        city: String,
+        population: u32,
+    }
+
+    // Streams canned responses, then fails if `error` is set.
+    #[derive(Default)]
+    struct FakeProvider {
+        responses: Vec<GeminiResponsePb>,
+        error: Option<&'static str>,
+        request: Mutex<Option<GeminiRequestPb>>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for FakeProvider {
+        async fn stream_generate(
+            &self,
+            gemini_request_pb: &GeminiRequestPb,
+            sender: UnboundedSender<GeminiResponsePb>,
+        ) -> Result<()> {
+            *self.request.lock().unwrap() = Some(gemini_request_pb.clone());
+            for response in &self.responses {
+                sender.send(response.clone())?;
+            }
+            if let Some(error) = self.error {
+                bail!(error);
+            }
+            Ok(())
+        }
+    }
+
+    fn text_response(text: &str, thought: bool) -> GeminiResponsePb {
+        GeminiResponsePb {
+            candidates: vec![CandidatePb {
+                content: Some(ContentPb {
+                    role: "model".into(),
+                    parts: vec![PartPb {
+                        text: Some(text.into()),
+                        thought,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn finish_response(finish_reason: &str) -> GeminiResponsePb {
+        GeminiResponsePb {
+            candidates: vec![CandidatePb {
+                finish_reason: Some(finish_reason.into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_text_is_joined_and_deserialized() {
+        let provider = FakeProvider {
+            responses: vec![
+                text_response("Looking it up.", true),
+                text_response("{\"city\": \"Oslo\", ", false),
+                text_response("\"population\": 700000}", false),
+                finish_response("STOP"),
+            ],
+            ..Default::default()
+        };
+
+        let answer: Answer = generate_json(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            answer,
+            Answer {
+                city: "Oslo".into(),
+                population: 700000,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn request_asks_for_json_matching_the_schema() {
+        let provider = FakeProvider {
+            responses: vec![text_response(
+                "{\"city\": \"Oslo\", \"population\": 1}",
+                false,
+            )],
+            ..Default::default()
+        };
+
+        let _: Answer = generate_json(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap();
+
+        let request = provider.request.lock().unwrap().clone().unwrap();
+        let generation_config = request.generation_config.unwrap();
+        assert_eq!(
+            generation_config.response_mime_type.as_deref(),
+            Some("application/json")
+        );
+        let response_schema = generation_config.response_schema.unwrap();
+        assert!(response_schema.properties.contains_key("city"));
+        assert!(response_schema.properties.contains_key("population"));
+    }
+
+    #[tokio::test]
+    async fn invalid_json_is_a_parse_error_with_the_text() {
+        let provider = FakeProvider {
+            responses: vec![text_response("{\"city\": \"Oslo\"", false)],
+            ..Default::default()
+        };
+
+        let error = generate_json::<Answer>(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap_err();
+
+        match error {
+            GenerateJsonError::Parse { text, .. } => assert_eq!(text, "{\"city\": \"Oslo\""),
+            error => panic!("expected a parse error, got {:?}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn json_of_the_wrong_shape_is_a_parse_error() {
+        let provider = FakeProvider {
+            responses: vec![text_response("{\"city\": \"Oslo\"}", false)],
+            ..Default::default()
+        };
+
+        let error = generate_json::<Answer>(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(error, GenerateJsonError::Parse { .. }),
+            "{:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_finish_reason_is_a_blocked_error() {
+        let provider = FakeProvider {
+            responses: vec![
+                text_response("{\"city\":", false),
+                finish_response("SAFETY"),
+            ],
+            ..Default::default()
+        };
+
+        let error = generate_json::<Answer>(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&error, GenerateJsonError::Blocked(reason) if reason == "SAFETY"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_prompt_is_a_blocked_error() {
+        let provider = FakeProvider {
+            responses: vec![GeminiResponsePb {
+                prompt_feedback: Some(PromptFeedbackPb {
+                    block_reason: Some("PROHIBITED_CONTENT".into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let error = generate_json::<Answer>(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&error, GenerateJsonError::Blocked(reason) if reason == "PROHIBITED_CONTENT"),
+            "{:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_request_is_a_request_error() {
+        let provider = FakeProvider {
+            error: Some("connection reset"),
+            ..Default::default()
+        };
+
+        let error = generate_json::<Answer>(&provider, &GeminiRequestPb::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(error, GenerateJsonError::Request(_)),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.to_string(), "Request failed: connection reset");
+    }
+}
@@ -0,0 +1,289 @@
+mod source;
+
+pub use source::{ContentsHistory, HistorySource, SessionHistory};
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::{
+    data::{self, CommandData},
+    gemini::api::{new_content_pb, new_gemini_request_pb},
+    llm,
+    proto::message::{history_policy_pb::Policy, ContentPb, SystemInstructionPb},
+};
+
+/// Messages older than the kept ones that have to pile up before the summary is updated.
+const SUMMARY_THRESHOLD: usize = 10;
+
+/// Sessions with a summary being written.
+static SUMMARIZING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+const SUMMARY_INSTRUCTION: &str = "You summarize conversations between a user and Solus, \
+    an assistant. Write a concise summary that keeps names, facts, decisions and open \
+    questions, so the conversation can continue from the summary alone.";
+
+/// A stored message, with consecutive chunks of a streamed response merged back together.
+pub(crate) struct Message {
+    /// Rowid of the last chunk.
+    pub rowid: i64,
+    pub content: ContentPb,
+}
+
+/// The session's history to send with the next request to `model`, trimmed according to
+/// its policy.
+pub async fn load(
+    command_data: &CommandData,
+    session_id: &str,
+    model: Option<&str>,
+) -> Result<Vec<ContentPb>> {
+    let messages = merge_chunks(data::get_messages(command_data, session_id).await?);
+    let policy = data::get_history_policy(command_data, session_id)
+        .await?
+        .and_then(|history_policy| history_policy.policy);
+
+    let messages = match policy {
+        None => messages,
+        Some(Policy::LastN(last_n)) => {
+            let start = turn_start(&messages, last_n as usize);
+            messages.into_iter().skip(start).collect()
+        }
+        Some(Policy::TokenBudget(token_budget)) => {
+            let start = token_budget_start(command_data, model, &messages, token_budget).await?;
+            messages.into_iter().skip(start).collect()
+        }
+        Some(Policy::SummarizeKeepLast(_)) => {
+            return summarized(command_data, session_id, messages).await;
+        }
+    };
+
+    Ok(contents(messages))
+}
+
+pub(crate) fn merge_chunks(rows: Vec<(i64, ContentPb)>) -> Vec<Message> {
+    let mut messages: Vec<Message> = vec![];
+
+    for (rowid, content) in rows {
+        match messages.last_mut() {
+            Some(last) if last.content.role == "model" && content.role == "model" => {
+                last.rowid = rowid;
+                last.content.parts.extend(content.parts);
+                last.content.cancelled |= content.cancelled;
+            }
+            _ => messages.push(Message { rowid, content }),
+        }
+    }
+
+    messages
+}
+
+fn contents(messages: Vec<Message>) -> Vec<ContentPb> {
+    messages
+        .into_iter()
+        .map(|message| message.content)
+        .collect()
+}
+
+// History has to start with a user message, a model turn or a function response
+// on their own would be missing what they answer.
+pub(crate) fn turn_starts(messages: &[Message]) -> Vec<usize> {
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(_, message)| message.content.role == "user")
+        .map(|(index, _)| index)
+        .collect()
+}
+
+// Index of the first turn within the last `last_n` messages, the current turn is always kept.
+fn turn_start(messages: &[Message], last_n: usize) -> usize {
+    let earliest = messages.len().saturating_sub(last_n);
+    let turn_starts = turn_starts(messages);
+
+    turn_starts
+        .iter()
+        .find(|start| **start >= earliest)
+        .or(turn_starts.last())
+        .copied()
+        .unwrap_or_default()
+}
+
+// Index of the earliest turn after which the history fits in `token_budget`.
+async fn token_budget_start(
+    command_data: &CommandData,
+    model: Option<&str>,
+    messages: &[Message],
+    token_budget: i32,
+) -> Result<usize> {
+    let turn_starts = turn_starts(messages);
+    let fits = |start: usize| async move {
+        let contents: Vec<ContentPb> = messages[start..]
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+        let tokens = command_data
+            .llm_provider
+            .count_tokens(model, &contents)
+            .await?;
+        Ok::<_, anyhow::Error>(tokens <= token_budget)
+    };
+
+    if turn_starts.is_empty() || fits(turn_starts[0]).await? {
+        return Ok(turn_starts.first().copied().unwrap_or_default());
+    }
+
+    // Fewer messages always take fewer tokens, so binary search for the first turn that fits.
+    // Counting calls the backend, this keeps it to a handful of requests.
+    let (mut low, mut high) = (1, turn_starts.len() - 1);
+    while low < high {
+        let middle = (low + high) / 2;
+        if fits(turn_starts[middle]).await? {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+
+    Ok(turn_starts[high])
+}
+
+// The stored summary followed by the messages it does not cover. Summaries are written
+// in the background after turns, see `summarize_in_background`.
+async fn summarized(
+    command_data: &CommandData,
+    session_id: &str,
+    messages: Vec<Message>,
+) -> Result<Vec<ContentPb>> {
+    let Some((summary, covered)) = data::get_latest_summary(command_data, session_id).await? else {
+        return Ok(contents(messages));
+    };
+
+    let mut history = vec![summary_content(&summary)];
+    history.extend(contents(
+        messages
+            .into_iter()
+            .filter(|message| message.rowid > covered)
+            .collect(),
+    ));
+
+    Ok(history)
+}
+
+/// Updates the session's rolling summary once enough messages fell out of the kept ones,
+/// for sessions with the summarize policy. Runs on its own task so the turn isn't held up.
+pub fn summarize_in_background(command_data: Arc<CommandData>, session_id: Arc<String>) {
+    // A turn can end while the previous turn's summary is still being written,
+    // a second summary would cover the same messages.
+    if !SUMMARIZING.lock().unwrap().insert(session_id.to_string()) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = update_summary(command_data, &session_id).await {
+            println!("Failed to summarize session {}: {}", session_id, e);
+        }
+        SUMMARIZING.lock().unwrap().remove(session_id.as_str());
+    });
+}
+
+async fn update_summary(command_data: Arc<CommandData>, session_id: &str) -> Result<()> {
+    let policy = data::get_history_policy(&command_data, session_id)
+        .await?
+        .and_then(|history_policy| history_policy.policy);
+    let Some(Policy::SummarizeKeepLast(keep_last)) = policy else {
+        return Ok(());
+    };
+
+    let messages = merge_chunks(data::get_messages(&command_data, session_id).await?);
+    let start = turn_start(&messages, keep_last as usize);
+    let summary = data::get_latest_summary(&command_data, session_id).await?;
+
+    let covered = summary.as_ref().map_or(0, |(_, rowid)| *rowid);
+    let unsummarized: Vec<&Message> = messages[..start]
+        .iter()
+        .filter(|message| message.rowid > covered)
+        .collect();
+
+    // Summarizing every turn would cost a request per turn, batch them instead.
+    if unsummarized.len() < SUMMARY_THRESHOLD {
+        return Ok(());
+    }
+
+    let text = summarize(
+        command_data.clone(),
+        summary.as_ref().map(|(text, _)| text.as_str()),
+        &unsummarized,
+    )
+    .await?;
+    let last_rowid = unsummarized.last().map_or(covered, |message| message.rowid);
+
+    data::add_summary(&command_data, session_id, &text, last_rowid).await
+}
+
+fn summary_content(summary: &str) -> ContentPb {
+    new_content_pb(
+        "user".into(),
+        format!("Summary of the conversation so far:\n{}", summary),
+    )
+}
+
+async fn summarize(
+    command_data: Arc<CommandData>,
+    previous_summary: Option<&str>,
+    messages: &[&Message],
+) -> Result<String> {
+    let mut transcript = String::new();
+    if let Some(previous_summary) = previous_summary {
+        transcript.push_str(&format!("Summary so far:\n{}\n\n", previous_summary));
+    }
+    transcript.push_str("Conversation:\n");
+    for message in messages {
+        transcript.push_str(&transcript_line(&message.content));
+    }
+
+    let mut gemini_request_pb =
+        new_gemini_request_pb(vec![new_content_pb("user".into(), transcript)]);
+    gemini_request_pb.system_instruction = Some(SystemInstructionPb {
+        parts: new_content_pb("system".into(), SUMMARY_INSTRUCTION.into()).parts,
+    });
+    gemini_request_pb.model = llm::summary_model_from_env();
+
+    llm::invoke_text(command_data, &gemini_request_pb).await
+}
+
+pub(crate) fn transcript_line(content: &ContentPb) -> String {
+    let speaker = match content.role.as_str() {
+        "model" => "Solus",
+        "function" => "Tool",
+        _ => "User",
+    };
+
+    let text = content
+        .parts
+        .iter()
+        .filter_map(|part| {
+            if let Some(text) = part.answer_text() {
+                Some(text.to_string())
+            } else if let Some(function_call) = &part.function_call {
+                Some(format!(
+                    "[calls {}({})]",
+                    function_call.name, function_call.args_json
+                ))
+            } else if let Some(executable_code) = &part.executable_code {
+                Some(format!("[runs code]\n{}", executable_code.code))
+            } else if let Some(code_execution_result) = &part.code_execution_result {
+                Some(format!("[code output]\n{}", code_execution_result.output))
+            } else {
+                part.function_response
+                    .as_ref()
+                    .map(|function_response| format!("[{} responded]", function_response.name))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!("{}: {}\n", speaker, text)
+}
@@ -0,0 +1,158 @@
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+
+use crate::{
+    data::{self, CommandData},
+    proto::message::ContentPb,
+};
+
+use super::merge_chunks;
+
+/// Where a generation's history comes from and where its new contents go.
+/// `llm::invoke` appends the request's contents and each streamed response chunk,
+/// then loads the history back for the next request.
+#[async_trait]
+pub trait HistorySource: Send + Sync {
+    /// Session that memories and usage are kept for, `None` for one-off generations.
+    fn session_id(&self) -> Option<&str>;
+
+    /// Whether the start of requests is cached for the session, see `context_cache::apply`.
+    /// Only for the session's own history, the cache is kept per session and other sources
+    /// send different contents for the same session.
+    fn caches_context(&self) -> bool {
+        false
+    }
+
+    /// Contents to send with the next request to `model`, oldest first. `None` is the
+    /// backend's configured model.
+    async fn load(&self, command_data: &CommandData, model: Option<&str>)
+        -> Result<Vec<ContentPb>>;
+
+    /// Adds a content to the history. Returns an id for `update`.
+    async fn append(&self, command_data: &CommandData, content: &ContentPb) -> Result<String>;
+
+    /// Replaces an appended content, e.g. to mark a response as cancelled.
+    async fn update(&self, command_data: &CommandData, id: &str, content: &ContentPb)
+        -> Result<()>;
+}
+
+/// A session's history in the database, trimmed by the session's history policy.
+pub struct SessionHistory {
+    session_id: String,
+}
+
+impl SessionHistory {
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl HistorySource for SessionHistory {
+    fn session_id(&self) -> Option<&str> {
+        Some(&self.session_id)
+    }
+
+    fn caches_context(&self) -> bool {
+        true
+    }
+
+    async fn load(
+        &self,
+        command_data: &CommandData,
+        model: Option<&str>,
+    ) -> Result<Vec<ContentPb>> {
+        super::load(command_data, &self.session_id, model).await
+    }
+
+    async fn append(&self, command_data: &CommandData, content: &ContentPb) -> Result<String> {
+        data::add_content(command_data, &self.session_id, content).await
+    }
+
+    async fn update(
+        &self,
+        command_data: &CommandData,
+        id: &str,
+        content: &ContentPb,
+    ) -> Result<()> {
+        data::update_content(command_data, id, content).await
+    }
+}
+
+/// History kept in memory for a single turn, starting from contents the caller supplies,
+/// e.g. messages fetched from a Discord channel. Nothing is saved.
+#[derive(Default)]
+pub struct ContentsHistory {
+    session_id: Option<String>,
+    contents: Mutex<Vec<ContentPb>>,
+}
+
+impl ContentsHistory {
+    pub fn new(contents: Vec<ContentPb>) -> Self {
+        Self {
+            session_id: None,
+            contents: Mutex::new(contents),
+        }
+    }
+
+    /// Keeps memories and usage for `session_id`, the contents still aren't saved to it.
+    pub fn with_session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+}
+
+#[async_trait]
+impl HistorySource for ContentsHistory {
+    fn session_id(&self) -> Option<&str> {
+        self.session_id.as_deref()
+    }
+
+    async fn load(
+        &self,
+        _command_data: &CommandData,
+        _model: Option<&str>,
+    ) -> Result<Vec<ContentPb>> {
+        let contents = self.contents.lock().unwrap().clone();
+        let rows = contents
+            .into_iter()
+            .enumerate()
+            .map(|(index, content)| (index as i64, content))
+            .collect();
+
+        Ok(merge_chunks(rows)
+            .into_iter()
+            .map(|message| message.content)
+            .collect())
+    }
+
+    async fn append(&self, _command_data: &CommandData, content: &ContentPb) -> Result<String> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.push(content.clone());
+        Ok((contents.len() - 1).to_string())
+    }
+
+    async fn update(
+        &self,
+        _command_data: &CommandData,
+        id: &str,
+        content: &ContentPb,
+    ) -> Result<()> {
+        let mut contents = self.contents.lock().unwrap();
+        match id
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| contents.get_mut(index))
+        {
+            Some(existing) => {
+                *existing = content.clone();
+                Ok(())
+            }
+            None => bail!("No content {} in this history.", id),
+        }
+    }
+}
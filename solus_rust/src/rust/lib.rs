@@ -1,16 +1,23 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
 use data::CommandData;
-use proto::message::ContentPb;
+use proto::message::{CachedContentPb, HistoryPolicyPb, UsageMetadataPb};
 use rusqlite::Connection;
 
 pub mod brave;
 pub mod composer;
+pub mod context_cache;
 pub mod data;
+pub mod documents;
 pub mod flux;
 pub mod gemini;
+pub mod history;
+pub mod llm;
+pub mod memory;
+pub mod openai;
 pub mod proto;
+pub mod tools;
 
 pub fn get_connection() -> Connection {
     match Connection::open("./history.db3") {
@@ -39,3 +46,65 @@ pub async fn create_session(command_data: Arc<CommandData>) -> Result<String> {
 pub async fn get_or_create_session(command_data: Arc<CommandData>, id: String) -> Result<String> {
     data::get_or_create_session(&command_data, id).await
 }
+
+pub async fn get_session_usage(
+    command_data: Arc<CommandData>,
+    session_id: String,
+) -> Result<UsageMetadataPb> {
+    data::get_session_usage(&command_data, &session_id).await
+}
+
+pub async fn get_daily_usage(
+    command_data: Arc<CommandData>,
+) -> Result<Vec<(String, UsageMetadataPb)>> {
+    data::get_daily_usage(&command_data).await
+}
+
+pub async fn get_history_policy(
+    command_data: Arc<CommandData>,
+    session_id: String,
+) -> Result<Option<HistoryPolicyPb>> {
+    data::get_history_policy(&command_data, &session_id).await
+}
+
+pub async fn set_history_policy(
+    command_data: Arc<CommandData>,
+    session_id: String,
+    history_policy: HistoryPolicyPb,
+) -> Result<()> {
+    data::set_history_policy(&command_data, &session_id, &history_policy).await
+}
+
+pub async fn remember(
+    command_data: Arc<CommandData>,
+    session_id: String,
+    text: String,
+) -> Result<()> {
+    memory::remember(&command_data, &session_id, &text).await
+}
+
+/// Adds a document to what `search_documents` can find, returns the number of chunks.
+pub async fn ingest_document(
+    command_data: Arc<CommandData>,
+    source: String,
+    bytes: Vec<u8>,
+) -> Result<usize> {
+    documents::ingest(&command_data, &source, bytes).await
+}
+
+pub async fn list_context_caches(command_data: Arc<CommandData>) -> Result<Vec<CachedContentPb>> {
+    command_data.llm_provider.list_caches().await
+}
+
+/// Makes the cache expire `ttl` from now.
+pub async fn extend_context_cache(
+    command_data: Arc<CommandData>,
+    name: String,
+    ttl: Duration,
+) -> Result<CachedContentPb> {
+    command_data.llm_provider.update_cache_ttl(&name, ttl).await
+}
+
+pub async fn delete_context_cache(command_data: Arc<CommandData>, name: String) -> Result<()> {
+    command_data.llm_provider.delete_cache(&name).await
+}
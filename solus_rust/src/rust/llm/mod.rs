@@ -0,0 +1,304 @@
+use std::{env, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    context_cache,
+    data::{self, CommandData},
+    gemini::{GeminiConfig, GeminiProvider},
+    history::{ContentsHistory, HistorySource},
+    memory,
+    openai::OpenAiProvider,
+    proto::message::{CachedContentPb, ContentPb, GeminiRequestPb, GeminiResponsePb},
+};
+
+/// What embedded text is used for, retrieval models embed documents and queries differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingTask {
+    Document,
+    Query,
+}
+
+/// A model backend. Requests and responses use the Gemini shaped protos,
+/// backends translate to and from their own wire format.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Streams the response to `gemini_request_pb` into `sender` as chunks arrive.
+    async fn stream_generate(
+        &self,
+        gemini_request_pb: &GeminiRequestPb,
+        sender: UnboundedSender<GeminiResponsePb>,
+    ) -> Result<()>;
+
+    /// Tokens `contents` would take up in a request to `model`, the configured model if
+    /// `None`. Backends without a counting endpoint use `estimate_tokens`.
+    async fn count_tokens(&self, _model: Option<&str>, contents: &[ContentPb]) -> Result<i32> {
+        Ok(estimate_tokens(contents))
+    }
+
+    /// One embedding per text, in the same order.
+    async fn embed(&self, _texts: &[String], _task: EmbeddingTask) -> Result<Vec<Vec<f32>>> {
+        bail!("This backend does not support embeddings.")
+    }
+
+    /// Whether `create_cache` and the other cache methods work with this backend.
+    fn supports_caching(&self) -> bool {
+        false
+    }
+
+    /// Caches the request's system instruction, tools and contents for `ttl`. Requests that
+    /// set `GeminiRequestPb::cached_content` to the cache's name start from them.
+    async fn create_cache(
+        &self,
+        _gemini_request_pb: &GeminiRequestPb,
+        _ttl: Duration,
+    ) -> Result<CachedContentPb> {
+        bail!("This backend does not support context caching.")
+    }
+
+    async fn list_caches(&self) -> Result<Vec<CachedContentPb>> {
+        bail!("This backend does not support context caching.")
+    }
+
+    /// Makes the cache expire `ttl` from now.
+    async fn update_cache_ttl(&self, _name: &str, _ttl: Duration) -> Result<CachedContentPb> {
+        bail!("This backend does not support context caching.")
+    }
+
+    async fn delete_cache(&self, _name: &str) -> Result<()> {
+        bail!("This backend does not support context caching.")
+    }
+}
+
+/// About four characters per token, attachments aren't counted.
+pub fn estimate_tokens(contents: &[ContentPb]) -> i32 {
+    let characters: usize = contents
+        .iter()
+        .flat_map(|content| content.parts.iter())
+        .map(|part| {
+            part.text.as_ref().map_or(0, String::len)
+                + part
+                    .function_call
+                    .as_ref()
+                    .map_or(0, |function_call| function_call.args_json.len())
+                + part
+                    .function_response
+                    .as_ref()
+                    .map_or(0, |function_response| function_response.response_json.len())
+        })
+        .sum();
+
+    (characters / 4) as i32
+}
+
+/// Picks the backend named by `LLM_PROVIDER` (`gemini` or `openai`), defaults to Gemini.
+/// `openai` works with any compatible server, point `OPENAI_BASE_URL` at e.g. llama.cpp.
+pub fn provider_from_env(reqwest_client: &Client) -> Result<Box<dyn LlmProvider>> {
+    let provider = env::var("LLM_PROVIDER").unwrap_or_else(|_| "gemini".into());
+
+    match provider.as_str() {
+        "gemini" => Ok(Box::new(GeminiProvider::new(
+            reqwest_client.clone(),
+            env::var("GEMINI_TOKEN").expect("GEMINI_TOKEN must be set."),
+            GeminiConfig::from_env(),
+        ))),
+        "openai" => Ok(Box::new(OpenAiProvider::new(
+            reqwest_client.clone(),
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".into()),
+            env::var("OPENAI_API_KEY").ok(),
+            env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o-mini".into()),
+            env::var("OPENAI_EMBEDDING_MODEL").unwrap_or_else(|_| "text-embedding-3-small".into()),
+        ))),
+        _ => bail!("Unknown LLM_PROVIDER: {}", provider),
+    }
+}
+
+/// Model for background work like summaries, `SUMMARY_MODEL` or a cheaper model of the
+/// `LLM_PROVIDER` backend. `None` leaves the backend's own model.
+pub fn summary_model_from_env() -> Option<String> {
+    let provider = env::var("LLM_PROVIDER").unwrap_or_else(|_| "gemini".into());
+
+    match env::var("SUMMARY_MODEL") {
+        Ok(model) => Some(model),
+        Err(_) if provider == "gemini" => Some("gemini-2.0-flash-lite".into()),
+        Err(_) => None,
+    }
+}
+
+/// Appends the request's contents to `history`, then generates with the whole history.
+/// Model responses are appended as they stream. Cancelling `cancellation_token` stops the
+/// generation and marks what was appended of the response as cancelled. Memories are
+/// recalled and usage is recorded for the history's session, if it has one. The start of
+/// the request is cached if the history caches context.
+pub async fn invoke(
+    command_data: Arc<CommandData>,
+    history: &dyn HistorySource,
+    gemini_request_pb: &GeminiRequestPb,
+    sender: UnboundedSender<GeminiResponsePb>,
+    cancellation_token: &CancellationToken,
+) -> Result<()> {
+    // Follow-up rounds (e.g. after a function call) may not carry new contents.
+    for new_content in &gemini_request_pb.contents {
+        history.append(&command_data, new_content).await?;
+    }
+
+    let mut session_request_pb = gemini_request_pb.clone();
+    session_request_pb.contents = history
+        .load(&command_data, gemini_request_pb.model.as_deref())
+        .await?;
+
+    if let Some(session_id) = history.session_id() {
+        if history.caches_context() {
+            // Without the cache the request is just more expensive.
+            if let Err(e) =
+                context_cache::apply(&command_data, session_id, &mut session_request_pb).await
+            {
+                println!("Failed to use a context cache: {}", e);
+            }
+        }
+        recall_memories(&command_data, session_id, &mut session_request_pb).await;
+    }
+
+    let (inner_tx, mut inner_rx) = mpsc::unbounded_channel();
+
+    // Dropping the provider's future closes its connection and inner_tx, which ends forward.
+    let generate = async {
+        tokio::select! {
+            generated = command_data
+                .llm_provider
+                .stream_generate(&session_request_pb, inner_tx) => generated,
+            _ = cancellation_token.cancelled() => Ok(()),
+        }
+    };
+
+    let forward = async {
+        let mut usage_metadata = None;
+        let mut last_saved = None;
+
+        while let Some(gemini_response_pb) = inner_rx.recv().await {
+            let model_content = gemini_response_pb
+                .candidates
+                .first()
+                .and_then(|candidate| candidate.content.as_ref());
+
+            if let Some(model_content) = model_content {
+                // if response has text, only save it if not empty
+                // else, save always (blocked candidates may have no parts at all)
+                let part = model_content.parts.first();
+                if part.is_some_and(|part| part.text.as_ref().is_none_or(|t| !t.is_empty())) {
+                    let id = history.append(&command_data, model_content).await?;
+                    last_saved = Some((id, model_content.clone()));
+                }
+            }
+
+            if gemini_response_pb.usage_metadata.is_some() {
+                usage_metadata = gemini_response_pb.usage_metadata;
+            }
+
+            sender.send(gemini_response_pb)?;
+        }
+
+        Ok::<_, anyhow::Error>((usage_metadata, last_saved))
+    };
+
+    let (generated, forwarded) = tokio::join!(generate, forward);
+    generated?;
+    let (usage_metadata, last_saved) = forwarded?;
+
+    if cancellation_token.is_cancelled() {
+        if let Some((id, mut content)) = last_saved {
+            content.cancelled = true;
+            history.update(&command_data, &id, &content).await?;
+        }
+    }
+
+    // Usage is cumulative, only the last report counts.
+    if let (Some(usage_metadata), Some(session_id)) = (usage_metadata, history.session_id()) {
+        data::add_usage(&command_data, session_id, &usage_metadata).await?;
+    }
+
+    Ok(())
+}
+
+// Adds the memories relevant to the latest prompt, follow-up rounds after function calls
+// recall the same ones.
+async fn recall_memories(
+    command_data: &CommandData,
+    session_id: &str,
+    gemini_request_pb: &mut GeminiRequestPb,
+) {
+    let query = gemini_request_pb
+        .contents
+        .iter()
+        .rev()
+        .find(|content| content.role == "user")
+        .map(|content| {
+            content
+                .parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<&str>>()
+                .join("\n")
+        })
+        .filter(|query| !query.is_empty());
+
+    if let Some(query) = query {
+        // Memories are a nice to have, the turn goes on without them.
+        match memory::recall(command_data, session_id, &query).await {
+            Ok(memories) => memory::add_to_request(gemini_request_pb, &memories),
+            Err(e) => println!("Failed to recall memories: {}", e),
+        }
+    }
+}
+
+/// Generates from the request's contents alone, nothing is loaded or saved.
+pub async fn invoke_simple(
+    command_data: Arc<CommandData>,
+    gemini_request_pb: &GeminiRequestPb,
+    sender: UnboundedSender<GeminiResponsePb>,
+) -> Result<()> {
+    invoke(
+        command_data,
+        &ContentsHistory::default(),
+        gemini_request_pb,
+        sender,
+        &CancellationToken::new(),
+    )
+    .await
+}
+
+/// Like `invoke_simple`, but waits for the whole response and returns its text.
+pub async fn invoke_text(
+    command_data: Arc<CommandData>,
+    gemini_request_pb: &GeminiRequestPb,
+) -> Result<String> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let generate = invoke_simple(command_data, gemini_request_pb, tx);
+
+    let collect = async {
+        let mut text = String::new();
+        while let Some(gemini_response_pb) = rx.recv().await {
+            let parts = gemini_response_pb
+                .candidates
+                .into_iter()
+                .filter_map(|candidate| candidate.content)
+                .flat_map(|content| content.parts);
+            for part in parts {
+                if let Some(part_text) = part.answer_text() {
+                    text.push_str(part_text);
+                }
+            }
+        }
+        text
+    };
+
+    let (generated, text) = tokio::join!(generate, collect);
+    generated?;
+
+    Ok(text)
+}
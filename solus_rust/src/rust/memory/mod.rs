@@ -0,0 +1,182 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::{
+    data::{self, CommandData},
+    history::{self, Message},
+    llm::EmbeddingTask,
+    proto::message::{GeminiRequestPb, PartPb, SystemInstructionPb},
+};
+
+/// Memories added to a request at most.
+const TOP_K: usize = 3;
+/// Memories less similar to the prompt than this are left out, even when fewer than `TOP_K`.
+const MIN_SIMILARITY: f32 = 0.5;
+// Embedding models only read so much, longer turns are cut.
+const MAX_MEMORY_CHARS: usize = 8000;
+
+/// Sessions with turns being remembered.
+static REMEMBERING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Saves a fact to remember in later conversations of the session.
+pub async fn remember(command_data: &CommandData, session_id: &str, text: &str) -> Result<()> {
+    let embedding = embed(command_data, text, EmbeddingTask::Document).await?;
+    data::add_memory(command_data, session_id, text, &embedding, None).await
+}
+
+/// Remembers the session's turns that aren't remembered yet, on its own task.
+pub fn remember_turns_in_background(command_data: Arc<CommandData>, session_id: Arc<String>) {
+    // Turns in the same channel can overlap, both would remember the same turns.
+    // The turns skipped here are remembered after the next one.
+    if !REMEMBERING.lock().unwrap().insert(session_id.to_string()) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = remember_turns(&command_data, &session_id).await {
+            println!("Failed to remember turns of session {}: {}", session_id, e);
+        }
+        REMEMBERING.lock().unwrap().remove(session_id.as_str());
+    });
+}
+
+async fn remember_turns(command_data: &CommandData, session_id: &str) -> Result<()> {
+    let remembered = data::get_last_remembered_rowid(command_data, session_id)
+        .await?
+        .unwrap_or_default();
+    let messages: Vec<Message> =
+        history::merge_chunks(data::get_messages(command_data, session_id).await?)
+            .into_iter()
+            .filter(|message| message.rowid > remembered)
+            .collect();
+
+    let mut turn_starts = history::turn_starts(&messages);
+    turn_starts.push(messages.len());
+
+    let turns: Vec<(String, i64)> = turn_starts
+        .windows(2)
+        .map(|window| {
+            let turn = &messages[window[0]..window[1]];
+            let text: String = turn
+                .iter()
+                .map(|message| history::transcript_line(&message.content))
+                .collect();
+            (
+                text.chars().take(MAX_MEMORY_CHARS).collect(),
+                turn[turn.len() - 1].rowid,
+            )
+        })
+        .collect();
+
+    if turns.is_empty() {
+        return Ok(());
+    }
+
+    let texts: Vec<String> = turns.iter().map(|(text, _)| text.clone()).collect();
+    let embeddings = command_data
+        .llm_provider
+        .embed(&texts, EmbeddingTask::Document)
+        .await?;
+
+    for ((text, rowid), embedding) in turns.iter().zip(embeddings) {
+        data::add_memory(command_data, session_id, text, &embedding, Some(*rowid)).await?;
+    }
+
+    Ok(())
+}
+
+/// The session's memories most similar to `query`, most similar first.
+pub async fn recall(
+    command_data: &CommandData,
+    session_id: &str,
+    query: &str,
+) -> Result<Vec<String>> {
+    let memories = data::get_memories(command_data, session_id).await?;
+    if memories.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let query_embedding = embed(command_data, query, EmbeddingTask::Query).await?;
+
+    let mut scored: Vec<(f32, String)> = memories
+        .into_iter()
+        .map(|(text, embedding)| (cosine_similarity(&query_embedding, &embedding), text))
+        .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    Ok(scored
+        .into_iter()
+        .take(TOP_K)
+        .map(|(_, text)| text)
+        .collect())
+}
+
+/// Adds recalled memories to the request's system instruction, or to its latest prompt
+/// when it starts from a context cache and can't have a system instruction of its own.
+pub fn add_to_request(gemini_request_pb: &mut GeminiRequestPb, memories: &[String]) {
+    if memories.is_empty() {
+        return;
+    }
+
+    let text = format!(
+        "Things you remember from earlier in this conversation, use them if relevant:\n{}",
+        memories
+            .iter()
+            .map(|memory| format!("- {}", memory.trim().replace('\n', "\n  ")))
+            .collect::<Vec<String>>()
+            .join("\n")
+    );
+
+    let part = PartPb {
+        text: Some(text),
+        ..Default::default()
+    };
+
+    let prompt = gemini_request_pb
+        .contents
+        .iter_mut()
+        .rev()
+        .find(|content| content.role == "user");
+    match prompt {
+        Some(prompt) if gemini_request_pb.cached_content.is_some() => prompt.parts.insert(0, part),
+        _ => gemini_request_pb
+            .system_instruction
+            .get_or_insert_with(SystemInstructionPb::default)
+            .parts
+            .push(part),
+    }
+}
+
+async fn embed(command_data: &CommandData, text: &str, task: EmbeddingTask) -> Result<Vec<f32>> {
+    let embeddings = command_data
+        .llm_provider
+        .embed(&[text.to_string()], task)
+        .await?;
+
+    embeddings
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No embedding returned"))
+}
+
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    // Embeddings from a different model can't be compared.
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::gemini::api::{FunctionDeclaration, FunctionParameter};
+
+#[derive(Serialize, Debug)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ChatTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    pub stream: bool,
+    pub stream_options: StreamOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Not part of OpenAI's API, but understood by llama.cpp and vLLM.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    /// `auto`, `none` or `required`.
+    Mode(String),
+    /// Has to call this function.
+    Function {
+        r#type: String,
+        function: ToolChoiceFunction,
+    },
+}
+
+#[derive(Serialize, Debug)]
+pub struct ToolChoiceFunction {
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Any JSON object.
+    JsonObject,
+    /// JSON matching `json_schema`.
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Serialize, Debug)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: Box<FunctionParameter>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StreamOptions {
+    /// Asks for a final chunk carrying token usage.
+    pub include_usage: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: Option<ChatContent>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ChatToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    /// Needed once a message carries anything besides text.
+    Parts(Vec<ChatContentPart>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+    File { file: ChatFile },
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImageUrl {
+    /// A web URL or a `data:` URL.
+    pub url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InputAudio {
+    /// Base64 encoded audio.
+    pub data: String,
+    /// `wav` or `mp3`.
+    pub format: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatFile {
+    pub filename: String,
+    /// A `data:` URL.
+    pub file_data: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatToolCall {
+    pub id: String,
+    pub r#type: String,
+    pub function: ChatFunctionCall,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatFunctionCall {
+    pub name: String,
+    /// JSON encoded arguments object.
+    pub arguments: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChatTool {
+    pub r#type: String,
+    pub function: FunctionDeclaration,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+    pub usage: Option<ChunkUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChunkUsage {
+    #[serde(default)]
+    pub prompt_tokens: i32,
+    #[serde(default)]
+    pub completion_tokens: i32,
+    #[serde(default)]
+    pub total_tokens: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChunkChoice {
+    pub delta: Option<ChunkDelta>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChunkDelta {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// A fragment of a streamed tool call, fragments with the same index belong to the same call.
+#[derive(Deserialize, Debug)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingResponse {
+    #[serde(default)]
+    pub data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingData {
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
@@ -0,0 +1,762 @@
+pub mod api;
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use api::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatContent, ChatContentPart, ChatFile,
+    ChatFunctionCall, ChatMessage, ChatTool, ChatToolCall, EmbeddingRequest, EmbeddingResponse,
+    ImageUrl, InputAudio, JsonSchemaFormat, ResponseFormat, StreamOptions, ToolCallDelta,
+    ToolChoice, ToolChoiceFunction,
+};
+use async_trait::async_trait;
+use base64::prelude::*;
+use reqwest::{header, Client};
+use reqwest_eventsource::{Error::StreamEnded, Event, EventSource};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::StreamExt;
+
+use crate::{
+    gemini::{function_declaration_from_pb, schema_from_pb},
+    llm::{EmbeddingTask, LlmProvider},
+    proto::message::{
+        CandidatePb, ContentPb, FunctionCallPb, FunctionCallingConfigPb, GeminiRequestPb,
+        GeminiResponsePb, GenerationConfigPb, PartPb, SystemInstructionPb, UsageMetadataPb,
+    },
+};
+
+/// Backend for any server exposing OpenAI's `/chat/completions`,
+/// e.g. llama.cpp, Ollama or vLLM.
+pub struct OpenAiProvider {
+    reqwest_client: Client,
+    /// Base URL including the version, e.g. `http://localhost:8080/v1`.
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    /// Model for `/embeddings`, not every server has one.
+    embedding_model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(
+        reqwest_client: Client,
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+        embedding_model: String,
+    ) -> Self {
+        Self {
+            reqwest_client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            embedding_model,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PendingToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn stream_generate(
+        &self,
+        gemini_request_pb: &GeminiRequestPb,
+        sender: UnboundedSender<GeminiResponsePb>,
+    ) -> Result<()> {
+        let url = format!("{}/chat/completions", self.base_url);
+
+        let generation_config = gemini_request_pb
+            .generation_config
+            .clone()
+            .unwrap_or_default();
+
+        // OpenAI has no allowed functions, only the allowed ones are sent instead.
+        let allowed_function_names = function_calling_config_pb(gemini_request_pb)
+            .filter(|function_calling_config_pb| function_calling_config_pb.mode == "ANY")
+            .map(|function_calling_config_pb| {
+                function_calling_config_pb.allowed_function_names.clone()
+            })
+            .unwrap_or_default();
+
+        let chat_request = ChatCompletionRequest {
+            model: gemini_request_pb
+                .model
+                .clone()
+                .unwrap_or_else(|| self.model.clone()),
+            messages: messages_from_pb(gemini_request_pb),
+            tools: gemini_request_pb
+                .tools
+                .iter()
+                .flat_map(|tool_pb| tool_pb.function_declarations.iter())
+                .filter(|function_declaration_pb| {
+                    allowed_function_names.is_empty()
+                        || allowed_function_names.contains(&function_declaration_pb.name)
+                })
+                .map(|function_declaration_pb| ChatTool {
+                    r#type: "function".into(),
+                    function: function_declaration_from_pb(function_declaration_pb),
+                })
+                .collect(),
+            tool_choice: tool_choice_from_pb(gemini_request_pb),
+            stream: true,
+            stream_options: StreamOptions {
+                include_usage: true,
+            },
+            temperature: generation_config.temperature,
+            top_p: generation_config.top_p,
+            top_k: generation_config.top_k,
+            max_tokens: generation_config.max_output_tokens,
+            response_format: response_format_from_pb(&generation_config),
+            stop: generation_config.stop_sequences,
+        };
+
+        let mut request_builder = self
+            .reqwest_client
+            .post(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(&chat_request);
+
+        if let Some(api_key) = &self.api_key {
+            request_builder = request_builder.bearer_auth(api_key);
+        }
+
+        // Tool calls arrive in fragments keyed by index, they are sent once complete.
+        let mut pending_tool_calls: BTreeMap<usize, PendingToolCall> = BTreeMap::new();
+
+        let mut es = EventSource::new(request_builder)?;
+        while let Some(event) = es.next().await {
+            match event {
+                Ok(Event::Message(message)) => {
+                    if message.data == "[DONE]" {
+                        es.close();
+                        break;
+                    }
+
+                    let chunk: ChatCompletionChunk = match serde_json::from_str(&message.data) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            bail!("ChatCompletionChunk: {}", e)
+                        }
+                    };
+
+                    if let Some(usage) = chunk.usage {
+                        sender.send(GeminiResponsePb {
+                            candidates: vec![],
+                            usage_metadata: Some(UsageMetadataPb {
+                                prompt_token_count: usage.prompt_tokens.into(),
+                                candidates_token_count: usage.completion_tokens.into(),
+                                total_token_count: usage.total_tokens.into(),
+                            }),
+                            prompt_feedback: None,
+                        })?;
+                    }
+
+                    let choice = match chunk.choices.into_iter().next() {
+                        Some(choice) => choice,
+                        None => {
+                            continue;
+                        }
+                    };
+
+                    if let Some(delta) = choice.delta {
+                        if let Some(text) = delta.content.filter(|text| !text.is_empty()) {
+                            sender.send(response_pb(
+                                vec![PartPb {
+                                    text: Some(text),
+                                    ..Default::default()
+                                }],
+                                None,
+                            ))?;
+                        }
+
+                        for tool_call_delta in delta.tool_calls.unwrap_or_default() {
+                            add_tool_call_delta(&mut pending_tool_calls, tool_call_delta);
+                        }
+                    }
+
+                    if let Some(finish_reason) = choice.finish_reason {
+                        flush_tool_calls(&mut pending_tool_calls, &sender)?;
+                        sender.send(response_pb(
+                            vec![],
+                            Some(finish_reason_to_pb(&finish_reason)),
+                        ))?;
+                    }
+                }
+                Err(err) => {
+                    match err {
+                        StreamEnded => {}
+                        _ => bail!("EventSource: {}", err),
+                    }
+                    es.close();
+                }
+                _ => {}
+            }
+        }
+
+        // Some servers end the stream without a finish_reason.
+        flush_tool_calls(&mut pending_tool_calls, &sender)?;
+
+        Ok(())
+    }
+
+    // Servers don't distinguish documents from queries.
+    async fn embed(&self, texts: &[String], _task: EmbeddingTask) -> Result<Vec<Vec<f32>>> {
+        let url = format!("{}/embeddings", self.base_url);
+
+        let mut request_builder = self.reqwest_client.post(url).json(&EmbeddingRequest {
+            model: self.embedding_model.clone(),
+            input: texts.to_vec(),
+        });
+
+        if let Some(api_key) = &self.api_key {
+            request_builder = request_builder.bearer_auth(api_key);
+        }
+
+        let response = request_builder.send().await?;
+        if !response.status().is_success() {
+            bail!(
+                "Embeddings failed with {}: {}",
+                response.status(),
+                response.text().await?
+            );
+        }
+
+        let mut data = response.json::<EmbeddingResponse>().await?.data;
+        if data.len() != texts.len() {
+            bail!("Expected {} embeddings, got {}", texts.len(), data.len());
+        }
+        data.sort_by_key(|embedding_data| embedding_data.index);
+
+        Ok(data
+            .into_iter()
+            .map(|embedding_data| embedding_data.embedding)
+            .collect())
+    }
+}
+
+fn function_calling_config_pb(
+    gemini_request_pb: &GeminiRequestPb,
+) -> Option<&FunctionCallingConfigPb> {
+    gemini_request_pb
+        .tool_config
+        .as_ref()
+        .and_then(|tool_config_pb| tool_config_pb.function_calling_config.as_ref())
+}
+
+fn tool_choice_from_pb(gemini_request_pb: &GeminiRequestPb) -> Option<ToolChoice> {
+    // Gemini's built-in tools like google_search have no counterpart here.
+    let has_functions = gemini_request_pb
+        .tools
+        .iter()
+        .any(|tool_pb| !tool_pb.function_declarations.is_empty());
+    if !has_functions {
+        return None;
+    }
+
+    let function_calling_config_pb = function_calling_config_pb(gemini_request_pb)?;
+    match (
+        function_calling_config_pb.mode.as_str(),
+        function_calling_config_pb.allowed_function_names.as_slice(),
+    ) {
+        ("ANY", [function_name]) => Some(ToolChoice::Function {
+            r#type: "function".into(),
+            function: ToolChoiceFunction {
+                name: function_name.clone(),
+            },
+        }),
+        ("ANY", _) => Some(ToolChoice::Mode("required".into())),
+        ("NONE", _) => Some(ToolChoice::Mode("none".into())),
+        _ => Some(ToolChoice::Mode("auto".into())),
+    }
+}
+
+fn add_tool_call_delta(
+    pending_tool_calls: &mut BTreeMap<usize, PendingToolCall>,
+    tool_call_delta: ToolCallDelta,
+) {
+    let pending = pending_tool_calls.entry(tool_call_delta.index).or_default();
+    if tool_call_delta.id.is_some() {
+        pending.id = tool_call_delta.id;
+    }
+    if let Some(function) = tool_call_delta.function {
+        if let Some(name) = function.name {
+            pending.name.push_str(&name);
+        }
+        if let Some(arguments) = function.arguments {
+            pending.arguments.push_str(&arguments);
+        }
+    }
+}
+
+fn flush_tool_calls(
+    pending_tool_calls: &mut BTreeMap<usize, PendingToolCall>,
+    sender: &UnboundedSender<GeminiResponsePb>,
+) -> Result<()> {
+    if pending_tool_calls.is_empty() {
+        return Ok(());
+    }
+
+    let parts = tool_call_parts(std::mem::take(pending_tool_calls))?;
+    sender.send(response_pb(parts, None))?;
+
+    Ok(())
+}
+
+// The assembled calls in index order.
+fn tool_call_parts(pending_tool_calls: BTreeMap<usize, PendingToolCall>) -> Result<Vec<PartPb>> {
+    pending_tool_calls
+        .into_values()
+        .map(|pending| {
+            Ok(PartPb {
+                function_call: Some(FunctionCallPb {
+                    name: pending.name,
+                    id: pending.id,
+                    args_json: args_from_json(&pending.arguments)?,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn response_pb(parts: Vec<PartPb>, finish_reason: Option<String>) -> GeminiResponsePb {
+    GeminiResponsePb {
+        candidates: vec![CandidatePb {
+            content: if parts.is_empty() {
+                None
+            } else {
+                Some(ContentPb {
+                    role: "model".into(),
+                    parts,
+                    cancelled: false,
+                })
+            },
+            finish_reason,
+            safety_ratings: vec![],
+            grounding_metadata: None,
+        }],
+        usage_metadata: None,
+        prompt_feedback: None,
+    }
+}
+
+fn finish_reason_to_pb(finish_reason: &str) -> String {
+    match finish_reason {
+        "length" => "MAX_TOKENS",
+        "content_filter" => "SAFETY",
+        _ => "STOP",
+    }
+    .into()
+}
+
+// Validates the streamed arguments, they are only complete once the call is flushed.
+fn args_from_json(arguments: &str) -> Result<String> {
+    if arguments.trim().is_empty() {
+        return Ok("{}".into());
+    }
+
+    let arguments: Map<String, Value> = match serde_json::from_str(arguments) {
+        Ok(v) => v,
+        Err(e) => {
+            bail!("Tool call arguments: {}", e)
+        }
+    };
+
+    Ok(Value::Object(arguments).to_string())
+}
+
+fn response_format_from_pb(generation_config_pb: &GenerationConfigPb) -> Option<ResponseFormat> {
+    if generation_config_pb.response_mime_type.as_deref() != Some("application/json") {
+        return None;
+    }
+
+    Some(match &generation_config_pb.response_schema {
+        Some(response_schema) => ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: "response".into(),
+                schema: Box::new(schema_from_pb(response_schema)),
+            },
+        },
+        None => ResponseFormat::JsonObject,
+    })
+}
+
+// Calls without an id (e.g. history written by another backend) are paired by name.
+fn tool_call_id(id: &Option<String>, name: &str) -> String {
+    id.clone().unwrap_or_else(|| name.to_string())
+}
+
+fn messages_from_pb(gemini_request_pb: &GeminiRequestPb) -> Vec<ChatMessage> {
+    let mut messages = vec![];
+
+    if let Some(system_message) =
+        system_message_from_pb(gemini_request_pb.system_instruction.as_ref())
+    {
+        messages.push(system_message);
+    }
+
+    for content_pb in &gemini_request_pb.contents {
+        match content_pb.role.as_str() {
+            "model" => {
+                // Thoughts and code execution parts have no Chat Completions equivalent.
+                let text = text_from_parts(&content_pb.parts);
+                let tool_calls: Vec<ChatToolCall> = content_pb
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.function_call.as_ref())
+                    .map(|function_call| ChatToolCall {
+                        id: tool_call_id(&function_call.id, &function_call.name),
+                        r#type: "function".into(),
+                        function: ChatFunctionCall {
+                            name: function_call.name.clone(),
+                            arguments: if function_call.args_json.is_empty() {
+                                "{}".into()
+                            } else {
+                                function_call.args_json.clone()
+                            },
+                        },
+                    })
+                    .collect();
+                // An assistant message needs content or tool calls.
+                if text.is_none() && tool_calls.is_empty() {
+                    continue;
+                }
+
+                // Streamed model turns are stored chunk by chunk, merge them back together.
+                match messages.last_mut() {
+                    Some(last) if last.role == "assistant" && last.tool_calls.is_empty() => {
+                        if let Some(text) = text {
+                            match &mut last.content {
+                                Some(ChatContent::Text(last_text)) => last_text.push_str(&text),
+                                _ => last.content = Some(ChatContent::Text(text)),
+                            }
+                        }
+                        last.tool_calls = tool_calls;
+                    }
+                    _ => messages.push(ChatMessage {
+                        role: "assistant".into(),
+                        content: text.map(ChatContent::Text),
+                        tool_calls,
+                        tool_call_id: None,
+                    }),
+                }
+            }
+            "function" => {
+                for function_response in content_pb
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.function_response.as_ref())
+                {
+                    messages.push(ChatMessage {
+                        role: "tool".into(),
+                        content: Some(ChatContent::Text(
+                            match function_response.response().unwrap_or_default() {
+                                Value::String(response) => response,
+                                response => response.to_string(),
+                            },
+                        )),
+                        tool_calls: vec![],
+                        tool_call_id: Some(tool_call_id(
+                            &function_response.id,
+                            &function_response.name,
+                        )),
+                    });
+                }
+            }
+            _ => messages.push(ChatMessage {
+                role: "user".into(),
+                content: user_content_from_parts(&content_pb.parts),
+                tool_calls: vec![],
+                tool_call_id: None,
+            }),
+        }
+    }
+
+    messages
+}
+
+fn system_message_from_pb(
+    system_instruction_pb: Option<&SystemInstructionPb>,
+) -> Option<ChatMessage> {
+    let text = text_from_parts(&system_instruction_pb?.parts)?;
+
+    Some(ChatMessage {
+        role: "system".into(),
+        content: Some(ChatContent::Text(text)),
+        tool_calls: vec![],
+        tool_call_id: None,
+    })
+}
+
+fn user_content_from_parts(parts: &[PartPb]) -> Option<ChatContent> {
+    if parts
+        .iter()
+        .all(|part| part.inline_data.is_none() && part.file_data.is_none())
+    {
+        return text_from_parts(parts).map(ChatContent::Text);
+    }
+
+    Some(ChatContent::Parts(
+        parts.iter().filter_map(content_part_from_pb).collect(),
+    ))
+}
+
+fn content_part_from_pb(part: &PartPb) -> Option<ChatContentPart> {
+    if let Some(text) = &part.text {
+        return Some(ChatContentPart::Text { text: text.clone() });
+    }
+
+    if let Some(blob) = &part.inline_data {
+        let data = BASE64_STANDARD.encode(&blob.data);
+        let data_url = format!("data:{};base64,{}", blob.mime_type, data);
+
+        return Some(match blob.mime_type.as_str() {
+            mime_type if mime_type.starts_with("image/") => ChatContentPart::ImageUrl {
+                image_url: ImageUrl { url: data_url },
+            },
+            "audio/wav" | "audio/x-wav" => ChatContentPart::InputAudio {
+                input_audio: InputAudio {
+                    data,
+                    format: "wav".into(),
+                },
+            },
+            "audio/mpeg" | "audio/mp3" => ChatContentPart::InputAudio {
+                input_audio: InputAudio {
+                    data,
+                    format: "mp3".into(),
+                },
+            },
+            _ => ChatContentPart::File {
+                file: ChatFile {
+                    filename: format!("attachment.{}", extension(&blob.mime_type)),
+                    file_data: data_url,
+                },
+            },
+        });
+    }
+
+    // Only images can be passed by URL.
+    if let Some(file_data) = &part.file_data {
+        if file_data.mime_type.starts_with("image/") {
+            return Some(ChatContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: file_data.file_uri.clone(),
+                },
+            });
+        }
+        return Some(ChatContentPart::Text {
+            text: format!(
+                "[{} attachment: {}]",
+                file_data.mime_type, file_data.file_uri
+            ),
+        });
+    }
+
+    None
+}
+
+fn extension(mime_type: &str) -> &str {
+    match mime_type.rsplit_once('/') {
+        Some((_, subtype)) => subtype,
+        None => "bin",
+    }
+}
+
+fn text_from_parts(parts: &[PartPb]) -> Option<String> {
+    let text: String = parts
+        .iter()
+        .filter_map(|part| part.answer_text())
+        .collect();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::message::{CodeExecutionResultPb, ExecutableCodePb, FunctionResponsePb};
+    use serde_json::json;
+
+    fn assemble(deltas: Value) -> Result<Vec<PartPb>> {
+        let deltas: Vec<ToolCallDelta> = serde_json::from_value(deltas).unwrap();
+        let mut pending_tool_calls = BTreeMap::new();
+        for tool_call_delta in deltas {
+            add_tool_call_delta(&mut pending_tool_calls, tool_call_delta);
+        }
+        tool_call_parts(pending_tool_calls)
+    }
+
+    fn function_call(part: &PartPb) -> &FunctionCallPb {
+        part.function_call.as_ref().unwrap()
+    }
+
+    fn model_content(parts: Vec<PartPb>) -> ContentPb {
+        ContentPb {
+            role: "model".into(),
+            parts,
+            ..Default::default()
+        }
+    }
+
+    fn text_part(text: &str) -> PartPb {
+        PartPb {
+            text: Some(text.into()),
+            ..Default::default()
+        }
+    }
+
+    fn request_pb(contents: Vec<ContentPb>) -> GeminiRequestPb {
+        GeminiRequestPb {
+            contents,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tool_call_fragments_across_deltas_are_joined() {
+        let parts = assemble(json!([
+            { "index": 0, "id": "call_1", "function": { "name": "web_", "arguments": "" } },
+            { "index": 0, "function": { "name": "search", "arguments": "{\"que" } },
+            { "index": 0, "function": { "arguments": "ry\": \"rust" } },
+            { "index": 0, "function": { "arguments": "\"}" } },
+        ]))
+        .unwrap();
+
+        assert_eq!(parts.len(), 1);
+        let function_call = function_call(&parts[0]);
+        assert_eq!(function_call.id.as_deref(), Some("call_1"));
+        assert_eq!(function_call.name, "web_search");
+        assert_eq!(function_call.args().unwrap()["query"], json!("rust"));
+    }
+
+    #[test]
+    fn interleaved_tool_calls_are_kept_apart_in_index_order() {
+        let parts = assemble(json!([
+            { "index": 1, "id": "call_b", "function": { "name": "generate_image" } },
+            { "index": 0, "id": "call_a", "function": { "name": "web_search" } },
+            { "index": 1, "function": { "arguments": "{\"prompt\":" } },
+            { "index": 0, "function": { "arguments": "{\"query\":" } },
+            { "index": 0, "function": { "arguments": "\"rust\"}" } },
+            { "index": 1, "function": { "arguments": "\"a crab\"}" } },
+        ]))
+        .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(function_call(&parts[0]).id.as_deref(), Some("call_a"));
+        assert_eq!(function_call(&parts[0]).args().unwrap()["query"], json!("rust"));
+        assert_eq!(function_call(&parts[1]).id.as_deref(), Some("call_b"));
+        assert_eq!(function_call(&parts[1]).args().unwrap()["prompt"], json!("a crab"));
+    }
+
+    #[test]
+    fn tool_call_without_arguments_has_empty_args() {
+        let parts = assemble(json!([
+            { "index": 0, "id": "call_1", "function": { "name": "list_documents" } },
+        ]))
+        .unwrap();
+
+        assert_eq!(function_call(&parts[0]).args_json, "{}");
+    }
+
+    #[test]
+    fn incomplete_tool_call_arguments_are_an_error() {
+        let result = assemble(json!([
+            { "index": 0, "id": "call_1", "function": { "name": "web_search", "arguments": "{\"query\":" } },
+        ]));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn model_contents_without_text_or_calls_are_skipped() {
+        let messages = messages_from_pb(&request_pb(vec![
+            model_content(vec![PartPb {
+                text: Some("Thinking it over.".into()),
+                thought: true,
+                ..Default::default()
+            }]),
+            model_content(vec![PartPb {
+                executable_code: Some(ExecutableCodePb {
+                    language: "PYTHON".into(),
+                    code: "print(1)".into(),
+                }),
+                ..Default::default()
+            }]),
+            model_content(vec![PartPb {
+                code_execution_result: Some(CodeExecutionResultPb {
+                    outcome: "OUTCOME_OK".into(),
+                    output: "1".into(),
+                }),
+                ..Default::default()
+            }]),
+        ]));
+
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn thoughts_are_left_out_of_assistant_text() {
+        let messages = messages_from_pb(&request_pb(vec![
+            model_content(vec![PartPb {
+                text: Some("Thinking it over.".into()),
+                thought: true,
+                ..Default::default()
+            }]),
+            model_content(vec![text_part("The answer")]),
+            model_content(vec![text_part(" is 1.")]),
+        ]));
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, "assistant");
+        assert!(matches!(
+            &messages[0].content,
+            Some(ChatContent::Text(text)) if text == "The answer is 1."
+        ));
+    }
+
+    #[test]
+    fn tool_calls_and_responses_are_paired_by_id() {
+        let call = FunctionCallPb {
+            name: "web_search".into(),
+            id: Some("call_1".into()),
+            args_json: "{\"query\":\"rust\"}".into(),
+            ..Default::default()
+        };
+        let messages = messages_from_pb(&request_pb(vec![
+            model_content(vec![PartPb {
+                function_call: Some(call),
+                ..Default::default()
+            }]),
+            ContentPb {
+                role: "function".into(),
+                parts: vec![PartPb {
+                    function_response: Some(FunctionResponsePb::new(
+                        "web_search",
+                        Some("call_1".into()),
+                        &json!("Found it."),
+                    )),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]));
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].content.is_none());
+        assert_eq!(messages[0].tool_calls[0].id, "call_1");
+        assert_eq!(messages[1].role, "tool");
+        assert_eq!(messages[1].tool_call_id.as_deref(), Some("call_1"));
+    }
+}
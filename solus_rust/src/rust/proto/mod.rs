@@ -1,3 +1,188 @@
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/proto.message.rs"));
 }
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use message::{FunctionCallPb, FunctionResponsePb, PartPb};
+
+impl PartPb {
+    /// The part's text if it belongs to the answer, the model's thoughts are left out.
+    pub fn answer_text(&self) -> Option<&str> {
+        if self.thought {
+            None
+        } else {
+            self.text.as_deref()
+        }
+    }
+}
+
+impl FunctionCallPb {
+    pub fn new(name: impl Into<String>, args: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            name: name.into(),
+            id: None,
+            args_json: serde_json::to_string(args)?,
+            ..Default::default()
+        })
+    }
+
+    /// The arguments as a JSON object, empty if the model sent none.
+    pub fn args(&self) -> Result<Map<String, Value>> {
+        if self.args_json.trim().is_empty() {
+            // Calls saved before args_json kept their arguments as strings.
+            return Ok(
+                self.legacy_args
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                    .collect()
+            );
+        }
+
+        serde_json::from_str(&self.args_json)
+            .with_context(|| format!("Invalid arguments for {}", self.name))
+    }
+
+    /// The arguments deserialized into a tool's own argument type.
+    pub fn parse_args<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(Value::Object(self.args()?))
+            .with_context(|| format!("Invalid arguments for {}", self.name))
+    }
+}
+
+impl FunctionResponsePb {
+    pub fn new(name: impl Into<String>, id: Option<String>, response: &Value) -> Self {
+        Self {
+            name: name.into(),
+            id,
+            response_json: response.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The response as JSON, `null` if there is none.
+    pub fn response(&self) -> Result<Value> {
+        if self.response_json.trim().is_empty() {
+            // Responses saved before response_json were plain text.
+            if self.legacy_response.is_empty() {
+                return Ok(Value::Null);
+            }
+            return Ok(Value::String(self.legacy_response.clone()));
+        }
+
+        serde_json::from_str(&self.response_json)
+            .with_context(|| format!("Invalid response for {}", self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct SearchArgs {
+        query: String,
+    }
+
+    #[test]
+    fn parse_args_reads_the_tool_argument_type() {
+        let function_call = FunctionCallPb::new("search", &serde_json::json!({ "query": "rust" })).unwrap();
+
+        let args: SearchArgs = function_call.parse_args().unwrap();
+
+        assert_eq!(args.query, "rust");
+    }
+
+    #[test]
+    fn parse_args_error_names_the_function_and_the_problem() {
+        let function_call = FunctionCallPb::new("search", &serde_json::json!({ "q": "rust" })).unwrap();
+
+        let error = function_call.parse_args::<SearchArgs>().unwrap_err();
+
+        let message = format!("{:#}", error);
+        assert!(message.contains("Invalid arguments for search"), "{}", message);
+        assert!(message.contains("missing field `query`"), "{}", message);
+    }
+
+    #[test]
+    fn args_are_empty_when_the_model_sent_none() {
+        let function_call = FunctionCallPb {
+            name: "search".into(),
+            ..Default::default()
+        };
+
+        assert!(function_call.args().unwrap().is_empty());
+    }
+
+    #[test]
+    fn args_fall_back_to_legacy_args() {
+        let function_call = FunctionCallPb {
+            name: "search".into(),
+            legacy_args: [("query".to_string(), "rust".to_string())].into(),
+            ..Default::default()
+        };
+
+        let args: SearchArgs = function_call.parse_args().unwrap();
+
+        assert_eq!(args.query, "rust");
+    }
+
+    #[test]
+    fn response_falls_back_to_legacy_response() {
+        let function_response = FunctionResponsePb {
+            name: "search".into(),
+            legacy_response: "Found it.".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(function_response.response().unwrap(), Value::String("Found it.".into()));
+    }
+
+    #[test]
+    fn response_is_null_when_there_is_none() {
+        let function_response = FunctionResponsePb::default();
+
+        assert_eq!(function_response.response().unwrap(), Value::Null);
+    }
+
+    // FunctionCallPb and FunctionResponsePb as they were saved before the JSON fields.
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct OldFunctionCallPb {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(map = "string, string", tag = "2")]
+        args: std::collections::HashMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct OldFunctionResponsePb {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(string, tag = "2")]
+        response: String,
+    }
+
+    #[test]
+    fn rows_saved_before_the_json_fields_still_decode() {
+        use prost::Message;
+
+        let old_call = OldFunctionCallPb {
+            name: "search".into(),
+            args: [("query".to_string(), "rust".to_string())].into(),
+        };
+        let function_call = FunctionCallPb::decode(old_call.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(function_call.args().unwrap()["query"], Value::String("rust".into()));
+
+        let old_response = OldFunctionResponsePb {
+            name: "search".into(),
+            response: "Found it.".into(),
+        };
+        let function_response = FunctionResponsePb::decode(
+            old_response.encode_to_vec().as_slice()
+        ).unwrap();
+        assert_eq!(function_response.response().unwrap(), Value::String("Found it.".into()));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{ json, Value };
+
+use crate::{
+    brave::BraveSearchTool,
+    data::CommandData,
+    documents::SearchDocumentsTool,
+    flux::GenerateImageTool,
+    proto::message::{ FunctionCallPb, FunctionDeclarationPb, ToolPb },
+};
+
+pub mod schema;
+
+/// Typed outcome of a tool call, shown to frontends and sent back to the model.
+#[derive(Debug, Clone)]
+pub enum ToolResult {
+    Image {
+        url: String,
+    },
+    Text(String),
+}
+
+impl ToolResult {
+    /// The value sent back to the model as the function response.
+    pub fn to_response(&self) -> Value {
+        match self {
+            ToolResult::Image { url } => json!({ "url": url }),
+            ToolResult::Text(text) => Value::String(text.clone()),
+        }
+    }
+}
+
+/// A function the model is allowed to call.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name the model uses to call this tool, must match the declaration.
+    fn name(&self) -> &'static str;
+
+    fn declaration(&self) -> FunctionDeclarationPb;
+
+    /// Runs the call, typically after reading its arguments with `FunctionCallPb::parse_args`.
+    /// An error is sent back to the model as the call's response, it doesn't end the turn.
+    async fn execute(
+        &self,
+        command_data: Arc<CommandData>,
+        function_call: &FunctionCallPb
+    ) -> Result<ToolResult>;
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.retain(|registered| registered.name() != tool.name());
+        self.tools.push(Box::new(tool));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.name() == name)
+            .map(|tool| tool.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.tools
+            .iter()
+            .map(|tool| tool.name())
+            .collect()
+    }
+
+    /// Declarations for every registered tool, ready to be sent with a request.
+    pub fn tools_pb(&self) -> Vec<ToolPb> {
+        if self.tools.is_empty() {
+            return vec![];
+        }
+
+        vec![ToolPb {
+            function_declarations: self.tools
+                .iter()
+                .map(|tool| tool.declaration())
+                .collect(),
+            ..Default::default()
+        }]
+    }
+}
+
+/// Registry with every tool Solus ships with.
+pub fn default_registry() -> ToolRegistry {
+    let mut registry = ToolRegistry::new();
+    registry.register(GenerateImageTool);
+    registry.register(BraveSearchTool);
+    registry.register(SearchDocumentsTool);
+    registry
+}
@@ -0,0 +1,148 @@
+use schemars::{
+    gen::SchemaSettings,
+    schema::{ InstanceType, Schema, SchemaObject, SingleOrVec },
+    JsonSchema,
+};
+use serde_json::Value;
+
+use crate::proto::message::{ FunctionDeclarationPb, FunctionParameterPb, FunctionParametersPb };
+
+// Formats Gemini understands, anything else (e.g. schemars' "uint32") is dropped.
+const SUPPORTED_FORMATS: &[&str] = &["int32", "int64", "float", "double", "date-time"];
+
+/// Declaration for a tool whose arguments deserialize into `T`.
+/// Doc comments on `T`'s fields become parameter descriptions.
+pub fn function_declaration<T: JsonSchema>(name: &str, description: &str) -> FunctionDeclarationPb {
+    let parameter = parameter_schema::<T>();
+
+    FunctionDeclarationPb {
+        name: name.into(),
+        description: description.into(),
+        parameters: Some(FunctionParametersPb {
+            r#type: "object".into(),
+            properties: parameter.properties,
+            required: parameter.required,
+        }),
+    }
+}
+
+/// Schema for values that serialize like `T`, e.g. a structured output's response schema.
+pub fn parameter_schema<T: JsonSchema>() -> FunctionParameterPb {
+    let mut settings = SchemaSettings::openapi3();
+    settings.inline_subschemas = true;
+    let root_schema = settings.into_generator().into_root_schema_for::<T>();
+    parameter_from_schema_object(&root_schema.schema)
+}
+
+fn parameter_from_schema(schema: &Schema) -> FunctionParameterPb {
+    match schema {
+        Schema::Object(schema_object) => parameter_from_schema_object(schema_object),
+        // `true` accepts anything, the closest Gemini has is a string.
+        Schema::Bool(_) =>
+            FunctionParameterPb {
+                r#type: "string".into(),
+                ..Default::default()
+            },
+    }
+}
+
+fn parameter_from_schema_object(schema_object: &SchemaObject) -> FunctionParameterPb {
+    let description = schema_object.metadata
+        .as_ref()
+        .and_then(|metadata| metadata.description.clone())
+        .unwrap_or_default();
+    let nullable =
+        schema_object.extensions.get("nullable") == Some(&Value::Bool(true)) ||
+        schema_object.has_type(InstanceType::Null);
+
+    let subschemas = schema_object.subschemas.as_ref();
+
+    // Documented or optional struct fields are wrapped as `allOf: [inner]`.
+    if let Some([inner]) = subschemas.and_then(|subschemas| subschemas.all_of.as_deref()) {
+        let mut parameter = parameter_from_schema(inner);
+        if !description.is_empty() {
+            parameter.description = description;
+        }
+        parameter.nullable |= nullable;
+        return parameter;
+    }
+
+    let mut enum_values: Vec<String> = schema_object.enum_values
+        .iter()
+        .flatten()
+        .map(enum_value)
+        .collect();
+    // Enums with documented variants are a `oneOf` with one value per variant.
+    for one_of in subschemas.and_then(|subschemas| subschemas.one_of.as_ref()).into_iter().flatten() {
+        if let Schema::Object(one_of) = one_of {
+            enum_values.extend(one_of.enum_values.iter().flatten().map(enum_value));
+        }
+    }
+
+    let r#type = match &schema_object.instance_type {
+        Some(SingleOrVec::Single(instance_type)) => type_name(instance_type),
+        Some(SingleOrVec::Vec(instance_types)) =>
+            instance_types
+                .iter()
+                .find(|instance_type| **instance_type != InstanceType::Null)
+                .map(type_name)
+                .unwrap_or("string"),
+        None if schema_object.object.is_some() => "object",
+        None => "string",
+    };
+
+    let items = schema_object.array
+        .as_ref()
+        .and_then(|array| array.items.as_ref())
+        .and_then(|items| {
+            match items {
+                SingleOrVec::Single(items) => Some(items.as_ref()),
+                SingleOrVec::Vec(items) => items.first(),
+            }
+        })
+        .map(|items| Box::new(parameter_from_schema(items)));
+
+    let (properties, required) = match &schema_object.object {
+        Some(object) =>
+            (
+                object.properties
+                    .iter()
+                    .map(|(name, schema)| (name.clone(), parameter_from_schema(schema)))
+                    .collect(),
+                object.required.iter().cloned().collect(),
+            ),
+        None => Default::default(),
+    };
+
+    FunctionParameterPb {
+        r#type: r#type.into(),
+        description,
+        format: schema_object.format
+            .clone()
+            .filter(|format| SUPPORTED_FORMATS.contains(&format.as_str())),
+        nullable,
+        enum_values,
+        items,
+        properties,
+        required,
+    }
+}
+
+fn type_name(instance_type: &InstanceType) -> &'static str {
+    match instance_type {
+        InstanceType::Null => "null",
+        InstanceType::Boolean => "boolean",
+        InstanceType::Object => "object",
+        InstanceType::Array => "array",
+        InstanceType::Number => "number",
+        InstanceType::String => "string",
+        InstanceType::Integer => "integer",
+    }
+}
+
+fn enum_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}